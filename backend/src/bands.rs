@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Price;
+
+// circuit breakers, as basis points either side of a price. a static band rejects orders
+// priced too far from the reference price, a dynamic band halts the book when a trade
+// would print too far from the last one
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PriceBands {
+    pub static_bps: Option<u32>,
+    pub dynamic_bps: Option<u32>,
+    pub halt_duration: Duration, // how long the halt and then the reopening auction last
+}
+
+impl PriceBands {
+    pub fn with_static(mut self, bps: u32) -> Self {
+        self.static_bps = Some(bps);
+        self
+    }
+
+    pub fn with_dynamic(mut self, bps: u32, halt_duration: Duration) -> Self {
+        self.dynamic_bps = Some(bps);
+        self.halt_duration = halt_duration;
+        self
+    }
+
+    pub fn within_static(&self, reference: Price, price: Price) -> bool {
+        within(self.static_bps, reference, price)
+    }
+
+    pub fn within_dynamic(&self, last_price: Price, price: Price) -> bool {
+        within(self.dynamic_bps, last_price, price)
+    }
+}
+
+// no band lets every price through
+fn within(bps: Option<u32>, reference: Price, price: Price) -> bool {
+    let Some(bps) = bps else {
+        return true;
+    };
+    let width = reference.units() as u128 * bps as u128 / 10_000;
+    (price.units().abs_diff(reference.units()) as u128) <= width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_edges() {
+        let bands = PriceBands::default().with_static(500);
+        let reference = Price::from(100);
+        assert!(bands.within_static(reference, Price::from(95)));
+        assert!(bands.within_static(reference, Price::from(105)));
+        assert!(!bands.within_static(reference, Price::new(10501, 2)));
+        assert!(!bands.within_static(reference, Price::new(9499, 2)));
+        // only the static band is set
+        assert!(bands.within_dynamic(reference, Price::from(1000)));
+    }
+}
@@ -1,9 +1,22 @@
 use axum::{
-    extract::State, routing::{get, post}, Json, Router
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRef, Path, Query, State,
+    },
+    http::StatusCode, response::Response, routing::{get, patch, post}, Json, Router
 };
-use backend::{OrderBook, order_generator::OrderGenerator};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use backend::{
+    AccountId, BookEvent, Command, Depth, Exchange, ExchangeError, Feed, FeedUpdate, FillReport,
+    Instrument, Journal, Level, NewOrder, Order, OrderBook, OrderType, Outcome, Price,
+    SequencedEvent, SessionState, Side, Snapshot as BookSnapshot, TimeInForce, TopOfBook,
+    Transaction, order_generator::OrderGenerator,
+};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{broadcast, RwLock};
 use tower_http::cors::{Any, CorsLayer};
 use serde::{Deserialize, Serialize};
 
@@ -11,18 +24,250 @@ use serde::{Deserialize, Serialize};
 pub struct CreateOrderResponse {
     pub status: String,
     pub order_id: u128,
+    pub fill: FillReport,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateOrder {
-    pub buy_order: bool,
-    pub price: u64,
+    pub side: Side,
+    #[serde(rename = "type", default)]
+    pub order_type: OrderType,
+    #[serde(default)]
+    pub price: Price, // "101.25" or 101.25, ignored for market orders
     pub quantity: u64,
+    #[serde(default)]
+    pub time_in_force: TimeInForceField,
+    pub expire_at: Option<u64>, // unix seconds, required for gtd
+    #[serde(default)]
+    pub account: AccountId,
+    #[serde(default)]
+    pub post_only: bool,
+    #[serde(default)]
+    pub reduce_only: bool,
+    pub trigger_price: Option<Price>, // required for stop orders
+    pub display_quantity: Option<u64>, // iceberg peak
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AmendOrder {
+    pub price: Price,
+    pub quantity: u64, // new total quantity, including any iceberg reserve
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DepthQuery {
+    #[serde(default = "default_depth")]
+    pub levels: usize,
+}
+
+fn default_depth() -> usize {
+    10
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EventsQuery {
+    #[serde(default)]
+    pub since: u64, // last sequence number the client has seen
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetSession {
+    pub state: SessionState,
+}
+
+// TimeInForce without the GTD payload, which comes in `expire_at` instead
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeInForceField {
+    #[default]
+    Gtc,
+    Ioc,
+    Fok,
+    Day,
+    Gtd,
+}
+
+// what a websocket client sends, e.g. {"op": "subscribe", "symbol": "ABC", "channel": "trades"}
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe(Subscription),
+    Unsubscribe(Subscription),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Subscription {
+    pub symbol: String,
+    #[serde(flatten)]
+    pub channel: Channel,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+pub enum Channel {
+    Trades,
+    Depth, // changed levels, quantity 0 once a level is empty
+    TopOfBook,
+    Orders { account: AccountId }, // events for one account's orders
+}
+
+// what the server sends. every subscription starts with a snapshot, then only gets
+// messages with a higher sequence number
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Snapshot {
+        #[serde(flatten)]
+        subscription: Subscription,
+        sequence: u64,
+        data: Snapshot,
+    },
+    Trade {
+        symbol: String,
+        sequence: u64,
+        trade: Transaction,
+    },
+    DepthUpdate {
+        symbol: String,
+        sequence: u64,
+        bids: Vec<Level>,
+        asks: Vec<Level>,
+    },
+    TopOfBook {
+        symbol: String,
+        sequence: u64,
+        #[serde(flatten)]
+        top: TopOfBook,
+    },
+    OrderUpdate {
+        symbol: String,
+        sequence: u64,
+        account: AccountId,
+        event: BookEvent,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Snapshot {
+    Trades(Vec<Transaction>), // the most recent ones, oldest first
+    Depth(Depth),
+    TopOfBook(TopOfBook),
+    Orders(Vec<Order>),
+}
+
+type SharedExchange = Arc<RwLock<Exchange>>;
+
+// only appended to under the exchange's write lock, so entries are in the order they ran
+type SharedJournal = Arc<Mutex<Journal>>;
+
+// fans book events out to websocket clients. publish while still holding the exchange's
+// write lock, so updates go out in the order the books changed
+#[derive(Clone)]
+struct MarketData {
+    feed: Arc<Mutex<Feed>>,
+    updates: broadcast::Sender<Arc<FeedUpdate>>,
 }
 
+#[derive(Clone)]
+struct AppState {
+    exchange: SharedExchange,
+    journal: SharedJournal,
+    market_data: MarketData,
+}
+
+impl FromRef<AppState> for SharedExchange {
+    fn from_ref(state: &AppState) -> Self {
+        state.exchange.clone()
+    }
+}
+
+impl FromRef<AppState> for SharedJournal {
+    fn from_ref(state: &AppState) -> Self {
+        state.journal.clone()
+    }
+}
+
+impl FromRef<AppState> for MarketData {
+    fn from_ref(state: &AppState) -> Self {
+        state.market_data.clone()
+    }
+}
+
+impl MarketData {
+    fn new() -> Self {
+        let (updates, _) = broadcast::channel(1024);
+        MarketData {
+            feed: Arc::new(Mutex::new(Feed::new())),
+            updates,
+        }
+    }
+
+    fn feed(&self) -> std::sync::MutexGuard<'_, Feed> {
+        // the feed is only touched under the exchange lock, so it can't be left half updated
+        self.feed.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn publish(&self, symbol: &str, ex: &Exchange) {
+        let Ok(book) = ex.book(symbol) else {
+            return;
+        };
+        if let Some(update) = self.feed().publish(symbol, book) {
+            // no receivers just means no one is connected
+            let _ = self.updates.send(Arc::new(update));
+        }
+    }
+
+    fn publish_all(&self, ex: &Exchange) {
+        for update in self.feed().publish_all(ex) {
+            let _ = self.updates.send(Arc::new(update));
+        }
+    }
+}
+
+const RECENT_TRADES: usize = 50;
+
+// where the journal and snapshots live unless CLOB_DATA_DIR says otherwise
+const DATA_DIR: &str = "clob-data";
+
+// how often the books are snapshotted, if anything was journaled since the last one
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
 #[tokio::main]
 async fn main() {
-    let ord_book = Arc::new(RwLock::new(build_order_book()));
+    let dir = std::env::var("CLOB_DATA_DIR").unwrap_or_else(|_| DATA_DIR.to_string());
+    let dir = PathBuf::from(dir);
+    let snapshot = BookSnapshot::latest(&dir).expect("failed to read the latest snapshot");
+    let (mut exchange, snapshot_offset) = match snapshot {
+        Some(snapshot) => (snapshot.exchange, snapshot.offset),
+        None => (build_exchange(), 0),
+    };
+    let (journal, entries) =
+        Journal::open(&dir, snapshot_offset).expect("failed to open the journal");
+    let journal = Mutex::new(journal);
+    let market_data = MarketData::new();
+    if snapshot_offset == 0 && entries.is_empty() {
+        seed_exchange(&mut exchange, &journal);
+    } else {
+        // every book back to exactly where it was when the last command ran
+        for entry in &entries {
+            let _ = exchange.apply(entry); // rejected again, just like the first time
+        }
+        println!(
+            "Recovered from the snapshot at offset {snapshot_offset} and {} journal entries",
+            entries.len()
+        );
+    }
+    market_data.publish_all(&exchange); // the seed or recovered orders
+    let state = AppState {
+        exchange: Arc::new(RwLock::new(exchange)),
+        journal: Arc::new(journal),
+        market_data,
+    };
+    tokio::spawn(run_timers(state.clone(), dir, snapshot_offset));
 
     let cors = CorsLayer::new()
         .allow_origin(
@@ -35,9 +280,19 @@ async fn main() {
 
     let app = Router::new()
         .route("/", get(home))
-        .route("/clob-stats", get(clob_stats))
-        .route("/orders", post(post_orders))
-        .with_state(ord_book)
+        .route("/instruments", get(instruments))
+        .route("/instruments/{symbol}", get(instrument))
+        .route("/instruments/{symbol}/clob-stats", get(clob_stats))
+        .route("/instruments/{symbol}/depth", get(depth))
+        .route("/instruments/{symbol}/events", get(events))
+        .route("/instruments/{symbol}/orders", post(post_orders))
+        .route(
+            "/instruments/{symbol}/orders/{id}",
+            patch(patch_order).delete(delete_order),
+        )
+        .route("/instruments/{symbol}/session", post(post_session))
+        .route("/ws", get(ws))
+        .with_state(state)
         .layer(cors);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -49,33 +304,452 @@ async fn home() -> &'static str {
     "CLOB API Homepage"
 }
 
-async fn clob_stats(State(ord_book): State<Arc<RwLock<OrderBook>>>) -> Json<OrderBook> {
-    let ob = ord_book.read().await;
-    Json(ob.clone())
+async fn instruments(State(exchange): State<SharedExchange>) -> Json<Vec<Instrument>> {
+    let ex = exchange.read().await;
+    Json(ex.instruments().cloned().collect())
 }
 
-async fn post_orders(State(ord_book): State<Arc<RwLock<OrderBook>>>, Json(payload): Json<CreateOrder>) -> Json<CreateOrderResponse> {
-    let mut ob = ord_book.write().await;
-    let ob = ob.buy(payload.buy_order, payload.price, payload.quantity as u128);
+async fn instrument(
+    State(exchange): State<SharedExchange>,
+    Path(symbol): Path<String>,
+) -> Result<Json<Instrument>, (StatusCode, String)> {
+    let ex = exchange.read().await;
+    let instrument = ex.instrument(&symbol).map_err(reject)?;
+    Ok(Json(instrument.clone()))
+}
 
-    Json(CreateOrderResponse { status: "ok".to_string(), order_id: ob.unwrap() })
+async fn clob_stats(
+    State(exchange): State<SharedExchange>,
+    Path(symbol): Path<String>,
+) -> Result<Json<OrderBook>, (StatusCode, String)> {
+    let ex = exchange.read().await;
+    let ob = ex.book(&symbol).map_err(reject)?;
+    Ok(Json(ob.public_view()))
 }
 
-fn build_order_book() -> OrderBook {
-    let mut ord_book = OrderBook::build();
+async fn depth(
+    State(exchange): State<SharedExchange>,
+    Path(symbol): Path<String>,
+    Query(query): Query<DepthQuery>,
+) -> Result<Json<Depth>, (StatusCode, String)> {
+    let ex = exchange.read().await;
+    let ob = ex.book(&symbol).map_err(reject)?;
+    Ok(Json(ob.depth(query.levels)))
+}
 
-    if let Some(ord_gen) = OrderGenerator::build(0.5, 0.5) {
-        for _ in 0..20 {
-            let (buy_sell, price) = ord_gen.gen_order(10.0);
-            if buy_sell {
-                ord_book.buy(true, price, 1);
-            } else {
-                ord_book.sell(false, price, 1);
+async fn events(
+    State(exchange): State<SharedExchange>,
+    Path(symbol): Path<String>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Json<Vec<SequencedEvent>>, (StatusCode, String)> {
+    let ex = exchange.read().await;
+    let ob = ex.book(&symbol).map_err(reject)?;
+    Ok(Json(ob.events_since(query.since).to_vec()))
+}
+
+async fn post_orders(
+    State(exchange): State<SharedExchange>,
+    State(journal): State<SharedJournal>,
+    State(market_data): State<MarketData>,
+    Path(symbol): Path<String>,
+    Json(payload): Json<CreateOrder>,
+) -> Result<Json<CreateOrderResponse>, (StatusCode, String)> {
+    let time_in_force = match (payload.time_in_force, payload.expire_at) {
+        (TimeInForceField::Gtc, _) => TimeInForce::Gtc,
+        (TimeInForceField::Ioc, _) => TimeInForce::Ioc,
+        (TimeInForceField::Fok, _) => TimeInForce::Fok,
+        (TimeInForceField::Day, _) => TimeInForce::Day,
+        (TimeInForceField::Gtd, Some(secs)) => {
+            TimeInForce::Gtd(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        }
+        (TimeInForceField::Gtd, None) => {
+            return Err((StatusCode::BAD_REQUEST, "gtd orders need expire_at".to_string()));
+        }
+    };
+
+    let order = NewOrder {
+        side: payload.side,
+        order_type: payload.order_type,
+        price: payload.price,
+        quantity: payload.quantity as u128,
+        time_in_force,
+        account: payload.account,
+        post_only: payload.post_only,
+        reduce_only: payload.reduce_only,
+        trigger_price: payload.trigger_price,
+        display_quantity: payload.display_quantity.map(|q| q as u128),
+    };
+
+    let mut ex = exchange.write().await;
+    let result = execute(&mut ex, &journal, &symbol, Command::Submit(order));
+    // even a rejected order may have swept expired ones off the book
+    market_data.publish(&symbol, &ex);
+    let Outcome::Submitted(fill) = result? else {
+        unreachable!("a submit is answered with its fill report");
+    };
+
+    Ok(Json(CreateOrderResponse {
+        status: "ok".to_string(),
+        order_id: fill.order_id,
+        fill,
+    }))
+}
+
+async fn patch_order(
+    State(exchange): State<SharedExchange>,
+    State(journal): State<SharedJournal>,
+    State(market_data): State<MarketData>,
+    Path((symbol, id)): Path<(String, u128)>,
+    Json(payload): Json<AmendOrder>,
+) -> Result<Json<Order>, (StatusCode, String)> {
+    let amend = Command::Amend {
+        id,
+        price: payload.price,
+        quantity: payload.quantity as u128,
+    };
+    let mut ex = exchange.write().await;
+    let result = execute(&mut ex, &journal, &symbol, amend);
+    market_data.publish(&symbol, &ex);
+    let Outcome::Amended(order) = result? else {
+        unreachable!("an amend is answered with the amended order");
+    };
+
+    Ok(Json(order))
+}
+
+// returns the cancelled order
+async fn delete_order(
+    State(exchange): State<SharedExchange>,
+    State(journal): State<SharedJournal>,
+    State(market_data): State<MarketData>,
+    Path((symbol, id)): Path<(String, u128)>,
+) -> Result<Json<Order>, (StatusCode, String)> {
+    let mut ex = exchange.write().await;
+    let result = execute(&mut ex, &journal, &symbol, Command::Cancel { id });
+    market_data.publish(&symbol, &ex);
+    let Outcome::Cancelled(order) = result? else {
+        unreachable!("a cancel is answered with the cancelled order");
+    };
+
+    Ok(Json(order))
+}
+
+// returns the trades of the uncross, if the transition ends an auction
+async fn post_session(
+    State(exchange): State<SharedExchange>,
+    State(journal): State<SharedJournal>,
+    State(market_data): State<MarketData>,
+    Path(symbol): Path<String>,
+    Json(payload): Json<SetSession>,
+) -> Result<Json<Vec<Transaction>>, (StatusCode, String)> {
+    let mut ex = exchange.write().await;
+    let result = execute(&mut ex, &journal, &symbol, Command::Transition(payload.state));
+    market_data.publish(&symbol, &ex);
+    let Outcome::Transitioned(trades) = result? else {
+        unreachable!("a transition is answered with its uncross trades");
+    };
+
+    Ok(Json(trades))
+}
+
+// sweep DAY/GTD orders and follow session schedules even when no new orders arrive.
+// only books with something due get a tick, so an idle exchange journals nothing.
+// every SNAPSHOT_INTERVAL the books are snapshotted too
+async fn run_timers(state: AppState, dir: PathBuf, mut snapshot_offset: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut last_snapshot = Instant::now();
+    loop {
+        interval.tick().await;
+        let mut ex = state.exchange.write().await;
+        let due: Vec<String> = ex
+            .symbols()
+            .filter(|symbol| ex.book(symbol).is_ok_and(|book| book.timers_due()))
+            .map(|symbol| symbol.to_string())
+            .collect();
+        for symbol in due {
+            if let Err((_, message)) = execute(&mut ex, &state.journal, &symbol, Command::Tick) {
+                println!("Tick for {symbol} failed: {message}");
             }
         }
-    } else {
-        println!("Failed to build OrderGenerator!");
+        state.market_data.publish_all(&ex);
+
+        if last_snapshot.elapsed() < SNAPSHOT_INTERVAL {
+            continue;
+        }
+        last_snapshot = Instant::now();
+        let snapshot = match take_snapshot(&ex, &state.journal, snapshot_offset) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => continue,
+            Err(err) => {
+                println!("Snapshot failed: {err}");
+                continue;
+            }
+        };
+        drop(ex); // commands carry on while the snapshot is written
+        let offset = snapshot.offset;
+        let saved = {
+            let dir = dir.clone();
+            tokio::task::spawn_blocking(move || snapshot.save(dir)).await
+        };
+        match saved {
+            Ok(Ok(_)) => {
+                snapshot_offset = offset;
+                let mut journal = lock_journal(&state.journal);
+                if let Err(err) = journal.archive(offset) {
+                    println!("Archiving the journal up to {offset} failed: {err}");
+                }
+            }
+            Ok(Err(err)) => println!("Snapshot failed: {err}"),
+            Err(err) => println!("Snapshot failed: {err}"),
+        }
+    }
+}
+
+// copy of every book as of the journal's current offset, None if nothing was journaled
+// since the last snapshot. the journal starts a new segment here, so the ones before
+// can be archived once the snapshot is saved. called under the exchange lock
+fn take_snapshot(
+    ex: &Exchange,
+    journal: &Mutex<Journal>,
+    last: u64,
+) -> io::Result<Option<BookSnapshot>> {
+    let mut journal = lock_journal(journal);
+    if journal.offset() == last {
+        return Ok(None);
+    }
+    journal.roll()?;
+    Ok(Some(BookSnapshot::new(journal.offset(), ex.clone())))
+}
+
+fn lock_journal(journal: &Mutex<Journal>) -> std::sync::MutexGuard<'_, Journal> {
+    // appends cut back a failed write themselves, so a poisoned journal is still whole
+    journal.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// journal a command and then run it. nothing runs unless it is on disk first, so a
+// restart can replay it. a command that fails on its own is turned away before that
+fn execute(
+    ex: &mut Exchange,
+    journal: &Mutex<Journal>,
+    symbol: &str,
+    command: Command,
+) -> Result<Outcome, (StatusCode, String)> {
+    ex.check(symbol, &command).map_err(reject)?;
+    let time = ex.now(symbol).map_err(reject)?;
+    let entry = lock_journal(journal)
+        .append(time, symbol, command)
+        .map_err(journal_failed)?;
+    ex.apply(&entry).map_err(reject)
+}
+
+async fn ws(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| stream_market_data(socket, state))
+}
+
+// one websocket client. each subscription remembers the sequence number it has
+// reached, so nothing from before its snapshot is sent twice
+async fn stream_market_data(mut socket: WebSocket, state: AppState) {
+    // subscribe before any snapshot is taken, so no update can fall in between
+    let mut updates = state.market_data.updates.subscribe();
+    let mut subscriptions: HashMap<Subscription, u64> = HashMap::new();
+    loop {
+        let messages = tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let ex = state.exchange.read().await;
+                    handle_client_message(&text, &ex, &mut subscriptions)
+                }
+                Some(Ok(_)) => continue, // pings are answered for us
+                _ => break,              // closed or broken
+            },
+            update = updates.recv() => match update {
+                Ok(update) => route_update(&update, &mut subscriptions),
+                // too slow to keep up: start every subscription over from a fresh snapshot
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let ex = state.exchange.read().await;
+                    let resubscribe: Vec<Subscription> = subscriptions.keys().cloned().collect();
+                    resubscribe
+                        .into_iter()
+                        .map(|subscription| subscribe(&ex, subscription, &mut subscriptions))
+                        .collect()
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        for message in messages {
+            let Ok(text) = serde_json::to_string(&message) else {
+                continue;
+            };
+            if socket.send(Message::Text(text.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn handle_client_message(
+    text: &str,
+    ex: &Exchange,
+    subscriptions: &mut HashMap<Subscription, u64>,
+) -> Vec<ServerMessage> {
+    match serde_json::from_str(text) {
+        Ok(ClientMessage::Subscribe(subscription)) => {
+            vec![subscribe(ex, subscription, subscriptions)]
+        }
+        Ok(ClientMessage::Unsubscribe(subscription)) => {
+            subscriptions.remove(&subscription);
+            Vec::new()
+        }
+        Err(err) => vec![ServerMessage::Error {
+            message: err.to_string(),
+        }],
+    }
+}
+
+fn subscribe(
+    ex: &Exchange,
+    subscription: Subscription,
+    subscriptions: &mut HashMap<Subscription, u64>,
+) -> ServerMessage {
+    let book = match ex.book(&subscription.symbol) {
+        Ok(book) => book,
+        Err(err) => {
+            subscriptions.remove(&subscription);
+            return ServerMessage::Error {
+                message: err.to_string(),
+            };
+        }
+    };
+    let data = match subscription.channel {
+        Channel::Trades => {
+            let trades = book.transactions();
+            Snapshot::Trades(trades[trades.len().saturating_sub(RECENT_TRADES)..].to_vec())
+        }
+        Channel::Depth => Snapshot::Depth(book.depth(usize::MAX)),
+        Channel::TopOfBook => {
+            let depth = book.depth(1);
+            Snapshot::TopOfBook(TopOfBook {
+                bid: depth.bids.first().copied(),
+                ask: depth.asks.first().copied(),
+            })
+        }
+        // there is no auth, so any client can ask for any account: displayed quantity only
+        Channel::Orders { account } => {
+            Snapshot::Orders(book.open_orders(account).iter().map(Order::redacted).collect())
+        }
+    };
+    subscriptions.insert(subscription.clone(), book.sequence());
+    ServerMessage::Snapshot {
+        subscription,
+        sequence: book.sequence(),
+        data,
     }
+}
 
-    ord_book
+// the messages `update` means for each subscription it hasn't already reached
+fn route_update(
+    update: &FeedUpdate,
+    subscriptions: &mut HashMap<Subscription, u64>,
+) -> Vec<ServerMessage> {
+    let mut messages = Vec::new();
+    for (subscription, reached) in subscriptions.iter_mut() {
+        if subscription.symbol != update.symbol || update.sequence <= *reached {
+            continue;
+        }
+        let events = update.events.iter().filter(|e| e.sequence > *reached);
+        let symbol = update.symbol.clone();
+        match subscription.channel {
+            Channel::Trades => {
+                for event in events {
+                    if let BookEvent::TradePrinted { trade, .. } = &event.event {
+                        messages.push(ServerMessage::Trade {
+                            symbol: symbol.clone(),
+                            sequence: event.sequence,
+                            trade: trade.clone(),
+                        });
+                    }
+                }
+            }
+            Channel::Depth => {
+                if !update.bids.is_empty() || !update.asks.is_empty() {
+                    messages.push(ServerMessage::DepthUpdate {
+                        symbol,
+                        sequence: update.sequence,
+                        bids: update.bids.clone(),
+                        asks: update.asks.clone(),
+                    });
+                }
+            }
+            Channel::TopOfBook => {
+                if let Some(top) = update.top_of_book {
+                    messages.push(ServerMessage::TopOfBook {
+                        symbol,
+                        sequence: update.sequence,
+                        top,
+                    });
+                }
+            }
+            Channel::Orders { account } => {
+                for event in events.filter(|e| e.event.involves(account)) {
+                    messages.push(ServerMessage::OrderUpdate {
+                        symbol: symbol.clone(),
+                        sequence: event.sequence,
+                        account,
+                        event: event.event.clone(),
+                    });
+                }
+            }
+        }
+        *reached = update.sequence;
+    }
+    messages
+}
+
+// unknown instruments are 404, every book rejection is a client mistake so 400
+fn reject(err: ExchangeError) -> (StatusCode, String) {
+    let status = match err {
+        ExchangeError::UnknownSymbol(_) => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, err.to_string())
+}
+
+// the command wasn't journaled, so it wasn't run either
+fn journal_failed(err: io::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("journal write failed: {err}"))
+}
+
+const TICK: Price = Price::new(1, 2);
+
+// empty books for every instrument, filled by seeding or by replaying the journal
+fn build_exchange() -> Exchange {
+    let mut exchange = Exchange::build();
+
+    for symbol in ["ABC", "XYZ"] {
+        // symbols are distinct so listing can't fail
+        let _ = exchange.list(Instrument::new(symbol, TICK, 1));
+    }
+
+    exchange
+}
+
+// random orders for a fresh exchange, journaled like any others so a restart finds
+// the same book
+fn seed_exchange(exchange: &mut Exchange, journal: &Mutex<Journal>) {
+    let Some(ord_gen) = OrderGenerator::build(0.5, 0.5) else {
+        println!("Failed to build OrderGenerator!");
+        return;
+    };
+    let symbols: Vec<String> = exchange.symbols().map(|s| s.to_string()).collect();
+    for symbol in symbols {
+        for _ in 0..20 {
+            let (side, price) = ord_gen.gen_order(10.0, TICK);
+            // prices are on the tick and quantity is always 1 so only the journal can fail
+            let submit = Command::Submit(NewOrder::limit(side, price, 1));
+            if let Err((_, message)) = execute(exchange, journal, &symbol, submit) {
+                println!("Failed to seed {symbol}: {message}");
+                return;
+            }
+        }
+    }
 }
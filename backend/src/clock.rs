@@ -0,0 +1,39 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+// where an OrderBook reads the time from. a manual clock makes expiry testable and
+// lets a replay reproduce the exact timestamps of the original run
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Clock {
+    #[default]
+    System,
+    Manual(SystemTime),
+}
+
+impl Clock {
+    pub fn now(&self) -> SystemTime {
+        match self {
+            Clock::System => SystemTime::now(),
+            Clock::Manual(time) => *time,
+        }
+    }
+
+    // only moves manual clocks, the system clock moves on its own
+    pub fn advance(&mut self, by: Duration) {
+        if let Clock::Manual(time) = self {
+            *time += by;
+        }
+    }
+}
+
+pub(crate) const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// DAY orders live until the next UTC midnight
+pub fn end_of_day(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    SystemTime::UNIX_EPOCH + Duration::from_secs((secs / SECS_PER_DAY + 1) * SECS_PER_DAY)
+}
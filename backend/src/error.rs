@@ -0,0 +1,149 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Price, SessionState, Side};
+
+// every way an OrderBook operation can be rejected
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderBookError {
+    ZeroQuantity,
+    UnknownOrderId(u128),
+    WrongSide { id: u128, side: Side }, // side is where the order actually rests
+    PriceOutOfBand(Price),
+    SelfTrade, // cancel newest would cancel the taker before it fills anything
+    BookHalted,
+    InsufficientLiquidity, // fill or kill can't be filled completely
+    ExpiryInPast,
+    PostOnlyWouldCross,
+    ReduceOnlyWouldIncrease,
+    MissingTriggerPrice,
+    PriceNotOnTick(Price),
+    TooManyDecimals(Price), // finer than the book's price scale
+    QuantityNotOnLot(u128),
+    QuantityBelowMinimum(u128),
+    QuantityAboveMaximum(u128),
+    NotionalBelowMinimum(Price),
+    InsufficientBalance,
+    NotAllowedInAuction, // market and immediate-or-cancel orders can't wait for the uncross
+    NotAllowedInSession(SessionState),
+    InvalidTransition { from: SessionState, to: SessionState },
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderBookError::ZeroQuantity => write!(f, "quantity can't be 0"),
+            OrderBookError::UnknownOrderId(id) => write!(f, "no resting order with id {}", id),
+            OrderBookError::WrongSide { id, side } => {
+                write!(f, "order {} rests on the {:?} side", id, side)
+            }
+            OrderBookError::PriceOutOfBand(price) => {
+                write!(f, "price {} is outside the allowed band", price)
+            }
+            OrderBookError::SelfTrade => {
+                write!(f, "order would trade against the same account")
+            }
+            OrderBookError::BookHalted => write!(f, "book is halted"),
+            OrderBookError::InsufficientLiquidity => {
+                write!(f, "not enough liquidity to fill the order completely")
+            }
+            OrderBookError::ExpiryInPast => write!(f, "expiry time has already passed"),
+            OrderBookError::PostOnlyWouldCross => write!(f, "post-only order would take liquidity"),
+            OrderBookError::ReduceOnlyWouldIncrease => {
+                write!(f, "reduce-only order would increase the position")
+            }
+            OrderBookError::MissingTriggerPrice => write!(f, "stop orders need a trigger price"),
+            OrderBookError::TooManyDecimals(price) => {
+                write!(f, "price {} has more decimal places than the book quotes", price)
+            }
+            OrderBookError::PriceNotOnTick(price) => {
+                write!(f, "price {} is not a multiple of the tick size", price)
+            }
+            OrderBookError::QuantityNotOnLot(quantity) => {
+                write!(f, "quantity {} is not a multiple of the lot size", quantity)
+            }
+            OrderBookError::QuantityBelowMinimum(quantity) => {
+                write!(f, "quantity {} is below the minimum order size", quantity)
+            }
+            OrderBookError::QuantityAboveMaximum(quantity) => {
+                write!(f, "quantity {} is above the maximum order size", quantity)
+            }
+            OrderBookError::NotionalBelowMinimum(notional) => {
+                write!(f, "notional {} is below the minimum", notional)
+            }
+            OrderBookError::InsufficientBalance => {
+                write!(f, "account can't cover the order with its available balance")
+            }
+            OrderBookError::NotAllowedInAuction => {
+                write!(f, "order type is not accepted during an auction")
+            }
+            OrderBookError::NotAllowedInSession(state) => {
+                write!(f, "not allowed while the session is {:?}", state)
+            }
+            OrderBookError::InvalidTransition { from, to } => {
+                write!(f, "session can't move from {:?} to {:?}", from, to)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderBookError {}
+
+// errors from routing an operation to one of the Exchange's books
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ExchangeError {
+    UnknownSymbol(String),
+    DuplicateSymbol(String),
+    Book(OrderBookError),
+}
+
+impl From<OrderBookError> for ExchangeError {
+    fn from(err: OrderBookError) -> Self {
+        ExchangeError::Book(err)
+    }
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::UnknownSymbol(symbol) => write!(f, "no instrument listed as {}", symbol),
+            ExchangeError::DuplicateSymbol(symbol) => write!(f, "{} is already listed", symbol),
+            ExchangeError::Book(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExchangeError::Book(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// why a string isn't a valid decimal Price
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ParsePriceError {
+    Empty,
+    InvalidDigit,
+    TooManyDecimals,
+    Overflow,
+}
+
+impl fmt::Display for ParsePriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsePriceError::Empty => write!(f, "price is empty"),
+            ParsePriceError::InvalidDigit => write!(f, "price is not a plain decimal number"),
+            ParsePriceError::TooManyDecimals => {
+                write!(f, "price has more than {} decimal places", Price::DECIMALS)
+            }
+            ParsePriceError::Overflow => write!(f, "price is too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParsePriceError {}
@@ -0,0 +1,78 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccountId, OrderBookError, Price, Side, Transaction};
+
+// one change to the visible book. applied in sequence order to a snapshot with a lower
+// sequence number, they rebuild every level order by order:
+// - OrderAdded joins the back of its price level
+// - OrderReduced and OrderExecuted set the displayed quantity to `remaining` and keep
+//   the order's place, an order at 0 leaves the book
+// - OrderCancelled removes the order
+// iceberg reserves are never shown, a refilled peak is an OrderAdded at the back.
+// stops are invisible until they trigger, one that fails its checks then is an
+// OrderRejected and never reaches the book. so is a market remainder a volatility
+// interruption cuts off.
+// every event names the accounts behind it, so a client can follow its own orders
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookEvent {
+    OrderAdded {
+        order_id: u128,
+        account: AccountId,
+        side: Side,
+        price: Price,
+        quantity: u128,
+    },
+    OrderReduced {
+        order_id: u128,
+        account: AccountId,
+        remaining: u128,
+    },
+    OrderCancelled {
+        order_id: u128,
+        account: AccountId,
+    },
+    OrderExecuted {
+        order_id: u128,
+        account: AccountId,
+        quantity: u128,
+        remaining: u128,
+    },
+    TradePrinted {
+        trade: Transaction,
+        taker_account: AccountId,
+        maker_account: AccountId,
+    },
+    OrderRejected {
+        order_id: u128,
+        account: AccountId,
+        reason: OrderBookError,
+    },
+}
+
+impl BookEvent {
+    // whether `account` is behind the event, on either side of a trade
+    pub fn involves(&self, account: AccountId) -> bool {
+        match *self {
+            BookEvent::OrderAdded { account: owner, .. }
+            | BookEvent::OrderReduced { account: owner, .. }
+            | BookEvent::OrderCancelled { account: owner, .. }
+            | BookEvent::OrderExecuted { account: owner, .. }
+            | BookEvent::OrderRejected { account: owner, .. } => owner == account,
+            BookEvent::TradePrinted {
+                taker_account,
+                maker_account,
+                ..
+            } => taker_account == account || maker_account == account,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct SequencedEvent {
+    pub sequence: u64, // starts at 1 and never skips
+    pub time: SystemTime,
+    pub event: BookEvent,
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExchangeError;
+use crate::{
+    Command, FillReport, JournalEntry, NewOrder, Order, OrderBook, OrderBookError, OrderType,
+    Outcome, Price, PriceBands, SessionState, TradingRules, Transaction,
+};
+
+// static description of something that can be traded
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: String,
+    pub rules: TradingRules, // tick, lot, size and notional limits, enforced by the book
+    pub min_price: Price, // orders priced outside [min_price, max_price] are rejected
+    pub max_price: Price,
+    #[serde(default)]
+    pub circuit_breakers: PriceBands, // percentage bands, enforced by the book
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+struct Listing {
+    instrument: Instrument,
+    book: OrderBook,
+}
+
+// many instruments, one OrderBook each, looked up by symbol
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Exchange {
+    listings: BTreeMap<String, Listing>,
+}
+
+impl Instrument {
+    pub fn new(symbol: &str, tick_size: impl Into<Price>, lot_size: u128) -> Self {
+        Instrument {
+            symbol: symbol.to_string(),
+            rules: TradingRules::new(tick_size, lot_size),
+            min_price: Price::ZERO,
+            max_price: Price::MAX,
+            circuit_breakers: PriceBands::default(),
+        }
+    }
+
+    pub fn with_price_band(mut self, min_price: impl Into<Price>, max_price: impl Into<Price>) -> Self {
+        self.min_price = min_price.into();
+        self.max_price = max_price.into();
+        self
+    }
+
+    pub fn with_rules(mut self, rules: TradingRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    // the number of decimal places its prices are quoted with
+    pub fn with_decimals(mut self, decimals: u32) -> Self {
+        self.rules = self.rules.with_decimals(decimals);
+        self
+    }
+
+    pub fn with_circuit_breakers(mut self, bands: PriceBands) -> Self {
+        self.circuit_breakers = bands;
+        self
+    }
+}
+
+impl Exchange {
+    pub fn build() -> Self {
+        Exchange {
+            listings: BTreeMap::new(),
+        }
+    }
+
+    // add an instrument with an empty book
+    pub fn list(&mut self, instrument: Instrument) -> Result<(), ExchangeError> {
+        if self.listings.contains_key(&instrument.symbol) {
+            return Err(ExchangeError::DuplicateSymbol(instrument.symbol));
+        }
+        let mut book = OrderBook::build_with_rules(instrument.rules.clone());
+        book.set_price_bands(instrument.circuit_breakers);
+        self.listings
+            .insert(instrument.symbol.clone(), Listing { instrument, book });
+        Ok(())
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.listings.keys().map(|s| s.as_str())
+    }
+
+    pub fn instruments(&self) -> impl Iterator<Item = &Instrument> {
+        self.listings.values().map(|l| &l.instrument)
+    }
+
+    pub fn instrument(&self, symbol: &str) -> Result<&Instrument, ExchangeError> {
+        self.listing(symbol).map(|l| &l.instrument)
+    }
+
+    pub fn book(&self, symbol: &str) -> Result<&OrderBook, ExchangeError> {
+        self.listing(symbol).map(|l| &l.book)
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> Result<&mut OrderBook, ExchangeError> {
+        self.listing_mut(symbol).map(|l| &mut l.book)
+    }
+
+    pub fn submit(&mut self, symbol: &str, order: NewOrder) -> Result<FillReport, ExchangeError> {
+        let listing = self.listing_mut(symbol)?;
+        check_new_order(&listing.instrument, &order)?;
+        Ok(listing.book.submit(order)?)
+    }
+
+    pub fn cancel(&mut self, symbol: &str, id: u128) -> Result<Order, ExchangeError> {
+        Ok(self.book_mut(symbol)?.cancel(id)?)
+    }
+
+    pub fn amend(
+        &mut self,
+        symbol: &str,
+        id: u128,
+        new_price: impl Into<Price>,
+        new_quantity: u128,
+    ) -> Result<Order, ExchangeError> {
+        let new_price = new_price.into();
+        let listing = self.listing_mut(symbol)?;
+        check_price(&listing.instrument, new_price)?;
+        Ok(listing.book.amend(id, new_price, new_quantity)?)
+    }
+
+    // the time a command for `symbol` runs at if it is journaled now
+    pub fn now(&self, symbol: &str) -> Result<SystemTime, ExchangeError> {
+        Ok(self.book(symbol)?.now())
+    }
+
+    // the instrument's and the book's checks that don't depend on what is on the book.
+    // a command that fails them would be rejected without touching anything, so it is
+    // turned away before it is journaled
+    pub fn check(&self, symbol: &str, command: &Command) -> Result<(), ExchangeError> {
+        let listing = self.listing(symbol)?;
+        match command {
+            Command::Submit(order) => check_new_order(&listing.instrument, order)?,
+            Command::Amend { price, .. } => check_price(&listing.instrument, *price)?,
+            _ => {}
+        }
+        Ok(listing.book.check(command)?)
+    }
+
+    // run a journaled command, with the same checks as the direct calls. replaying a
+    // journal into freshly listed instruments rebuilds every book
+    pub fn apply(&mut self, entry: &JournalEntry) -> Result<Outcome, ExchangeError> {
+        self.check(&entry.symbol, &entry.command)?;
+        let book = self.book_mut(&entry.symbol)?;
+        Ok(book.apply(entry.time, &entry.command)?)
+    }
+
+    // expiry sweep over every book
+    pub fn expire_orders(&mut self) -> Vec<(String, Order)> {
+        let mut expired = Vec::new();
+        for (symbol, listing) in self.listings.iter_mut() {
+            for order in listing.book.expire_orders() {
+                expired.push((symbol.clone(), order));
+            }
+        }
+        expired
+    }
+
+    // move one book to another session state, returning the uncross trades if any
+    pub fn transition(
+        &mut self,
+        symbol: &str,
+        state: SessionState,
+    ) -> Result<Vec<Transaction>, ExchangeError> {
+        Ok(self.book_mut(symbol)?.transition(state)?)
+    }
+
+    // move every scheduled book along its timetable. a book whose schedule asks for a
+    // transition it can't make stays where it is
+    pub fn run_schedules(&mut self) -> Vec<(String, Transaction)> {
+        let mut trades = Vec::new();
+        for (symbol, listing) in self.listings.iter_mut() {
+            for trade in listing.book.run_schedule().unwrap_or_default() {
+                trades.push((symbol.clone(), trade));
+            }
+        }
+        trades
+    }
+
+    pub(crate) fn forget_events(&mut self) {
+        for listing in self.listings.values_mut() {
+            listing.book.forget_events();
+        }
+    }
+
+    fn listing(&self, symbol: &str) -> Result<&Listing, ExchangeError> {
+        self.listings
+            .get(symbol)
+            .ok_or_else(|| ExchangeError::UnknownSymbol(symbol.to_string()))
+    }
+
+    fn listing_mut(&mut self, symbol: &str) -> Result<&mut Listing, ExchangeError> {
+        self.listings
+            .get_mut(symbol)
+            .ok_or_else(|| ExchangeError::UnknownSymbol(symbol.to_string()))
+    }
+}
+
+// orders priced outside [min_price, max_price] never reach the book
+fn check_price(instrument: &Instrument, price: Price) -> Result<(), OrderBookError> {
+    if price < instrument.min_price || price > instrument.max_price {
+        return Err(OrderBookError::PriceOutOfBand(price));
+    }
+    Ok(())
+}
+
+// a stop's trigger too, one the instrument can never trade at would never fire
+fn check_new_order(instrument: &Instrument, order: &NewOrder) -> Result<(), OrderBookError> {
+    if let Some(trigger) = order.trigger_price {
+        check_price(instrument, trigger)?;
+    }
+    match order.order_type {
+        OrderType::Market | OrderType::StopMarket => Ok(()),
+        _ => check_price(instrument, order.price),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Side;
+
+    fn exchange() -> Exchange {
+        let mut ex = Exchange::build();
+        ex.list(Instrument::new("ABC", 1, 1)).unwrap();
+        ex.list(Instrument::new("XYZ", 1, 1).with_price_band(50, 150)).unwrap();
+        ex
+    }
+
+    #[test]
+    fn test_books_are_independent() {
+        let mut ex = exchange();
+        ex.submit("ABC", NewOrder::limit(Side::Ask, 100, 5)).unwrap();
+
+        // same price on another instrument doesn't match
+        let report = ex.submit("XYZ", NewOrder::limit(Side::Bid, 100, 5)).unwrap();
+        assert!(report.fills.is_empty());
+
+        let report = ex.submit("ABC", NewOrder::limit(Side::Bid, 100, 5)).unwrap();
+        assert_eq!(report.filled_quantity, 5);
+        assert_eq!(ex.book("XYZ").unwrap().best_bid(), Some(Price::from(100)));
+        assert_eq!(ex.book("ABC").unwrap().best_bid(), None);
+        assert_eq!(ex.symbols().collect::<Vec<_>>(), vec!["ABC", "XYZ"]);
+    }
+
+    #[test]
+    fn test_routing_errors() {
+        let mut ex = exchange();
+
+        assert_eq!(
+            ex.submit("NOPE", NewOrder::limit(Side::Bid, 100, 5)),
+            Err(ExchangeError::UnknownSymbol("NOPE".to_string()))
+        );
+        assert_eq!(
+            ex.list(Instrument::new("ABC", 1, 1)),
+            Err(ExchangeError::DuplicateSymbol("ABC".to_string()))
+        );
+        assert_eq!(
+            ex.submit("XYZ", NewOrder::limit(Side::Bid, 151, 5)),
+            Err(ExchangeError::Book(OrderBookError::PriceOutOfBand(Price::from(151))))
+        );
+        assert_eq!(
+            ex.submit("XYZ", NewOrder::stop_market(Side::Ask, 20, 5)),
+            Err(ExchangeError::Book(OrderBookError::PriceOutOfBand(Price::from(20))))
+        );
+        assert_eq!(
+            ex.submit("XYZ", NewOrder::stop_limit(Side::Bid, 160, 100, 5)),
+            Err(ExchangeError::Book(OrderBookError::PriceOutOfBand(Price::from(160))))
+        );
+        assert_eq!(
+            ex.cancel("ABC", 3),
+            Err(ExchangeError::Book(OrderBookError::UnknownOrderId(3)))
+        );
+    }
+
+    #[test]
+    fn test_listing_applies_instrument_rules() {
+        let mut ex = Exchange::build();
+        ex.list(Instrument::new("LOT", 5, 100)).unwrap();
+
+        assert_eq!(ex.book("LOT").unwrap().rules(), &TradingRules::new(5, 100));
+        assert_eq!(
+            ex.submit("LOT", NewOrder::limit(Side::Bid, 100, 150)),
+            Err(ExchangeError::Book(OrderBookError::QuantityNotOnLot(150)))
+        );
+        assert!(ex.submit("LOT", NewOrder::limit(Side::Bid, 100, 200)).is_ok());
+    }
+
+    #[test]
+    fn test_check_is_stateless() {
+        let mut ex = exchange();
+        let submit = |order| Command::Submit(order);
+        let amend = Command::Amend {
+            id: 7,
+            price: Price::from(151),
+            quantity: 1,
+        };
+        assert_eq!(
+            ex.check("NOPE", &Command::Tick),
+            Err(ExchangeError::UnknownSymbol("NOPE".to_string()))
+        );
+        assert_eq!(
+            ex.check("ABC", &submit(NewOrder::limit(Side::Bid, 100, 0))),
+            Err(ExchangeError::Book(OrderBookError::ZeroQuantity))
+        );
+        assert_eq!(
+            ex.check("XYZ", &amend),
+            Err(ExchangeError::Book(OrderBookError::PriceOutOfBand(Price::from(151))))
+        );
+
+        // whether it trades, rests or is refused depends on the book, that's for `apply`
+        ex.transition("ABC", SessionState::Halted).unwrap();
+        assert_eq!(ex.check("ABC", &submit(NewOrder::market(Side::Bid, 5))), Ok(()));
+        assert_eq!(ex.check("ABC", &Command::Cancel { id: 7 }), Ok(()));
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BookEvent, Exchange, Level, MatchingPolicy, OrderBook, Price, SequencedEvent, Side,
+};
+
+// best level on each side
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TopOfBook {
+    pub bid: Option<Level>,
+    pub ask: Option<Level>,
+}
+
+// everything that changed on one book since its previous update
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FeedUpdate {
+    pub symbol: String,
+    pub sequence: u64, // of the last event in `events`
+    pub events: Vec<SequencedEvent>,
+    pub bids: Vec<Level>, // every level that changed, best first, quantity 0 once empty
+    pub asks: Vec<Level>,
+    pub top_of_book: Option<TopOfBook>, // only when it changed
+}
+
+// turns each book's event feed into the updates market data clients subscribe to.
+// publishing after every command keeps updates in the order the books changed
+#[derive(Debug, Clone, Default)]
+pub struct Feed {
+    published: HashMap<String, u64>, // last sequence per symbol
+    locations: HashMap<(String, u128), (Side, Price)>, // visible orders
+    tops: HashMap<String, TopOfBook>,
+}
+
+impl Feed {
+    pub fn new() -> Self {
+        Feed::default()
+    }
+
+    // None if nothing happened on the book since the last update
+    pub fn publish<P: MatchingPolicy>(
+        &mut self,
+        symbol: &str,
+        book: &OrderBook<P>,
+    ) -> Option<FeedUpdate> {
+        let since = match self.published.get(symbol) {
+            Some(since) => *since,
+            None => {
+                // orders restored from a snapshot, or whose OrderAdded is no longer kept,
+                // are only known from the book itself
+                for ord in book.orders().filter(|ord| !ord.order_type.is_stop()) {
+                    let location = (ord.side, ord.price);
+                    self.locations.insert((symbol.to_string(), ord.id), location);
+                }
+                self.published.insert(symbol.to_string(), 0);
+                0
+            }
+        };
+        let events = book.events_since(since).to_vec();
+        let sequence = events.last()?.sequence;
+        self.published.insert(symbol.to_string(), sequence);
+
+        let (mut bids, mut asks) = (BTreeSet::new(), BTreeSet::new());
+        let mut touch = |(side, price): (Side, Price)| match side {
+            Side::Bid => bids.insert(price),
+            Side::Ask => asks.insert(price),
+        };
+        for event in &events {
+            let key = |id: u128| (symbol.to_string(), id);
+            match event.event {
+                BookEvent::OrderAdded { order_id, side, price, .. } => {
+                    self.locations.insert(key(order_id), (side, price));
+                    touch((side, price));
+                }
+                BookEvent::OrderReduced { order_id, remaining, .. }
+                | BookEvent::OrderExecuted { order_id, remaining, .. } => {
+                    if let Some(location) = self.locations.get(&key(order_id)) {
+                        touch(*location);
+                    }
+                    if remaining == 0 {
+                        self.locations.remove(&key(order_id));
+                    }
+                }
+                BookEvent::OrderCancelled { order_id, .. } => {
+                    if let Some(location) = self.locations.remove(&key(order_id)) {
+                        touch(location);
+                    }
+                }
+                BookEvent::TradePrinted { .. } | BookEvent::OrderRejected { .. } => {}
+            }
+        }
+
+        let depth = book.depth(1);
+        let top = TopOfBook {
+            bid: depth.bids.first().copied(),
+            ask: depth.asks.first().copied(),
+        };
+        let top_of_book = (self.tops.get(symbol) != Some(&top)).then_some(top);
+        self.tops.insert(symbol.to_string(), top);
+
+        Some(FeedUpdate {
+            symbol: symbol.to_string(),
+            sequence,
+            events,
+            bids: bids.iter().rev().map(|price| book.level(Side::Bid, *price)).collect(),
+            asks: asks.iter().map(|price| book.level(Side::Ask, *price)).collect(),
+            top_of_book,
+        })
+    }
+
+    // an update for every book that changed
+    pub fn publish_all(&mut self, exchange: &Exchange) -> Vec<FeedUpdate> {
+        let symbols: Vec<String> = exchange.symbols().map(|s| s.to_string()).collect();
+        symbols
+            .iter()
+            .filter_map(|symbol| self.publish(symbol, exchange.book(symbol).ok()?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NewOrder;
+
+    fn level(price: u64, quantity: u128, orders: usize) -> Level {
+        Level {
+            price: Price::from(price),
+            quantity,
+            orders,
+        }
+    }
+
+    #[test]
+    fn test_updates_carry_changed_levels() {
+        let mut book = OrderBook::build();
+        let mut feed = Feed::new();
+        book.buy(99, 2).unwrap();
+        book.sell(101, 3).unwrap();
+        book.sell(102, 1).unwrap();
+
+        let update = feed.publish("ABC", &book).unwrap();
+        assert_eq!(update.sequence, 3);
+        assert_eq!(update.bids, vec![level(99, 2, 1)]);
+        assert_eq!(update.asks, vec![level(101, 3, 1), level(102, 1, 1)]);
+        assert!(update.top_of_book.is_some());
+        assert_eq!(feed.publish("ABC", &book), None);
+
+        // the taker never rests, so only the ask it took changes
+        book.buy(101, 3).unwrap();
+        let update = feed.publish("ABC", &book).unwrap();
+        assert!(update.bids.is_empty());
+        assert_eq!(update.asks, vec![level(101, 0, 0)]);
+        let top = update.top_of_book.unwrap();
+        assert_eq!(top.ask, Some(level(102, 1, 1)));
+
+        // deeper changes leave the top alone
+        book.sell(103, 1).unwrap();
+        assert_eq!(feed.publish("ABC", &book).unwrap().top_of_book, None);
+    }
+
+    #[test]
+    fn test_restored_orders_update_their_levels() {
+        let mut book = OrderBook::build();
+        book.buy(99, 2).unwrap();
+        book.sell(101, 3).unwrap();
+        book.forget_events(); // as a snapshot restores it
+
+        let mut feed = Feed::new();
+        assert_eq!(feed.publish("ABC", &book), None);
+        book.cancel(0).unwrap();
+        let update = feed.publish("ABC", &book).unwrap();
+        assert_eq!(update.bids, vec![level(99, 0, 0)]);
+        assert!(update.asks.is_empty());
+    }
+
+    #[test]
+    fn test_events_name_accounts() {
+        let mut book = OrderBook::build();
+        let mut feed = Feed::new();
+        book.submit(NewOrder::limit(Side::Ask, 100, 5).for_account(7)).unwrap();
+        feed.publish("ABC", &book);
+
+        // the taker never rests, only the trade tells its account
+        book.submit(NewOrder::market(Side::Bid, 5).for_account(8)).unwrap();
+        let update = feed.publish("ABC", &book).unwrap();
+        let involved = |account| update.events.iter().filter(|e| e.event.involves(account)).count();
+        assert_eq!(involved(7), 2); // executed and traded
+        assert_eq!(involved(8), 1);
+        assert_eq!(involved(9), 0);
+        assert!(feed.locations.is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{FillReport, NewOrder, Order, Price, SessionState, Transaction};
+
+// where segments and snapshots go once a newer snapshot covers them
+pub const ARCHIVE_DIR: &str = "archive";
+
+// everything that changes a book. together with the time it ran at, a command always
+// does the same thing to the same book, so replaying them rebuilds it exactly
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Submit(NewOrder),
+    Amend { id: u128, price: Price, quantity: u128 },
+    Cancel { id: u128 },
+    Transition(SessionState),
+    Tick, // expiry sweep and session schedule
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub offset: u64, // starts at 1 and never skips
+    pub time: SystemTime,
+    pub symbol: String,
+    pub command: Command,
+}
+
+// what a command did, one variant per command
+#[derive(PartialEq, Debug, Clone)]
+pub enum Outcome {
+    Submitted(FillReport),
+    Amended(Order),
+    Cancelled(Order),
+    Transitioned(Vec<Transaction>), // uncross trades, if any
+    Ticked {
+        expired: Vec<Order>,
+        trades: Vec<Transaction>,
+    },
+}
+
+// append only log of commands, one JSON line each. an entry is on disk before `append`
+// returns, so a command is only run (and acknowledged) once it would survive a crash.
+// only commands that pass `Exchange::check` are appended. one the book still rejects
+// is journaled all the same, replaying it rejects it again.
+// the log is split into segment files in one directory, each named after the offset of
+// its first entry, so the part a snapshot covers can be archived in whole files
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    file: File,  // the segment being appended to
+    offset: u64, // of the last entry
+    len: u64,    // bytes of whole entries in the current segment
+}
+
+impl Journal {
+    // open or create the journal in `dir`, returning the entries after offset `after`,
+    // where a snapshot left off. a crash while appending can leave a torn last line,
+    // that entry was never run so it is cut off. anything else unreadable is an error,
+    // as is a gap between `after` and the first entry
+    pub fn open(dir: impl AsRef<Path>, after: u64) -> io::Result<(Journal, Vec<JournalEntry>)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let segments = segments(&dir)?;
+        let mut entries = Vec::new();
+        let mut offset = after;
+        let mut tail = None;
+        for (i, (_, path)) in segments.iter().enumerate() {
+            let last = i + 1 == segments.len();
+            let (segment, len) = read_segment(path, last)?;
+            for entry in segment.into_iter().filter(|e| e.offset > after) {
+                if entry.offset != offset + 1 {
+                    return Err(invalid(format!(
+                        "journal skips from offset {offset} to {}",
+                        entry.offset
+                    )));
+                }
+                offset = entry.offset;
+                entries.push(entry);
+            }
+            if last {
+                tail = Some((path, len));
+            }
+        }
+
+        let (file, len) = match tail {
+            Some((path, len)) => {
+                let file = OpenOptions::new().append(true).open(path)?;
+                file.set_len(len)?;
+                (file, len)
+            }
+            None => (create_segment(&dir, offset + 1)?, 0),
+        };
+        let journal = Journal {
+            dir,
+            file,
+            offset,
+            len,
+        };
+        Ok((journal, entries))
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    // write and sync the next entry. on failure the segment is cut back to its last
+    // whole entry, so later appends don't land behind a torn one
+    pub fn append(
+        &mut self,
+        time: SystemTime,
+        symbol: &str,
+        command: Command,
+    ) -> io::Result<JournalEntry> {
+        let entry = JournalEntry {
+            offset: self.offset + 1,
+            time,
+            symbol: symbol.to_string(),
+            command,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let written = self
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data());
+        if let Err(err) = written {
+            let _ = self.file.set_len(self.len);
+            return Err(err);
+        }
+        self.offset = entry.offset;
+        self.len += line.len() as u64;
+        Ok(entry)
+    }
+
+    // start a new segment, so everything up to the current offset is in older ones
+    pub fn roll(&mut self) -> io::Result<()> {
+        if self.len == 0 {
+            return Ok(()); // the current segment already starts at the next entry
+        }
+        self.file = create_segment(&self.dir, self.offset + 1)?;
+        self.len = 0;
+        Ok(())
+    }
+
+    // move every segment holding nothing after `offset` into the archive directory,
+    // returning how many were moved. the current segment always stays
+    pub fn archive(&mut self, offset: u64) -> io::Result<usize> {
+        let segments = segments(&self.dir)?;
+        let archive = self.dir.join(ARCHIVE_DIR);
+        let mut archived = 0;
+        for pair in segments.windows(2) {
+            let ((_, path), (next_start, _)) = (&pair[0], &pair[1]);
+            if *next_start > offset + 1 {
+                break;
+            }
+            fs::create_dir_all(&archive)?;
+            fs::rename(path, archive.join(path.file_name().unwrap_or_default()))?;
+            archived += 1;
+        }
+        if archived > 0 {
+            sync_dir(&self.dir)?;
+        }
+        Ok(archived)
+    }
+}
+
+// renames and new files only survive a crash once their directory is synced
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+pub(crate) fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// segment files in `dir` by the offset of their first entry
+fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().is_some_and(|ext| ext == "log")
+            && let Some(start) = path.file_stem().and_then(|s| s.to_str()?.parse().ok())
+        {
+            segments.push((start, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn create_segment(dir: &Path, start: u64) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(dir.join(format!("{start:020}.log")))?;
+    sync_dir(dir)?;
+    Ok(file)
+}
+
+// the entries in a segment and the length they take up. only the last segment may
+// end in a torn line
+fn read_segment(path: &Path, last: bool) -> io::Result<(Vec<JournalEntry>, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    let mut len = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        match serde_json::from_str::<JournalEntry>(line.trim_end()) {
+            Ok(entry) if line.ends_with('\n') => entries.push(entry),
+            _ if last && reader.fill_buf()?.is_empty() => break, // torn tail
+            _ => {
+                return Err(invalid(format!(
+                    "corrupt journal entry in {} at byte {len}",
+                    path.display()
+                )));
+            }
+        }
+        len += read as u64;
+    }
+    Ok((entries, len))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Side;
+    use std::time::Duration;
+
+    // an empty scratch directory per test and process
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_journal_survives_torn_tail() {
+        let dir = temp_dir("journal-torn");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let (mut journal, entries) = Journal::open(&dir, 0).unwrap();
+        assert!(entries.is_empty());
+        let order = NewOrder::limit(Side::Bid, Price::new(10125, 2), 5).for_account(3);
+        let submit = journal.append(time, "ABC", Command::Submit(order)).unwrap();
+        let cancel = journal.append(time, "ABC", Command::Cancel { id: 0 }).unwrap();
+        assert_eq!(cancel.offset, 2);
+        drop(journal);
+
+        // half an entry, as if the process died mid write
+        let path = dir.join(format!("{:020}.log", 1));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"offset\":3,\"ti").unwrap();
+        drop(file);
+
+        let (mut journal, entries) = Journal::open(&dir, 0).unwrap();
+        assert_eq!(entries, vec![submit, cancel]);
+        assert_eq!(journal.append(time, "ABC", Command::Tick).unwrap().offset, 3);
+        drop(journal);
+        let (_, entries) = Journal::open(&dir, 0).unwrap();
+        assert_eq!(entries.len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_segments_roll_and_archive() {
+        let dir = temp_dir("journal-segments");
+        let time = SystemTime::UNIX_EPOCH;
+        let (mut journal, _) = Journal::open(&dir, 0).unwrap();
+        for _ in 0..3 {
+            journal.append(time, "ABC", Command::Tick).unwrap();
+        }
+        journal.roll().unwrap();
+        journal.roll().unwrap(); // nothing new to split off
+        journal.append(time, "ABC", Command::Tick).unwrap();
+        journal.roll().unwrap();
+        journal.append(time, "ABC", Command::Tick).unwrap();
+        assert_eq!(segments(&dir).unwrap().len(), 3);
+
+        // a snapshot at 3 covers the first segment only
+        assert_eq!(journal.archive(3).unwrap(), 1);
+        assert_eq!(journal.archive(3).unwrap(), 0);
+        drop(journal);
+        let (journal, entries) = Journal::open(&dir, 3).unwrap();
+        assert_eq!(entries.iter().map(|e| e.offset).collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(journal.offset(), 5);
+        assert!(dir.join(ARCHIVE_DIR).join(format!("{:020}.log", 1)).exists());
+
+        // without the snapshot the archived entries are missing
+        assert!(Journal::open(&dir, 0).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccountId, OrderBookError};
+
+// a book trades its base asset against a quote asset. base amounts are quantities,
+// quote amounts are in Price units (10^-Price::DECIMALS), the same as a notional
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Asset {
+    Base,
+    Quote,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: u128,
+    pub locked: u128, // held by resting or in-flight orders
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Account {
+    pub base: Balance,
+    pub quote: Balance,
+}
+
+// balances per account. buys lock quote and sells lock base when they are submitted,
+// fills move locked funds between the two sides of the trade
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    accounts: HashMap<AccountId, Account>,
+}
+
+impl Account {
+    fn balance_mut(&mut self, asset: Asset) -> &mut Balance {
+        match asset {
+            Asset::Base => &mut self.base,
+            Asset::Quote => &mut self.quote,
+        }
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Ledger {
+            accounts: HashMap::new(),
+        }
+    }
+
+    // unknown accounts have empty balances
+    pub fn account(&self, account: AccountId) -> Account {
+        self.accounts.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn deposit(&mut self, account: AccountId, asset: Asset, amount: u128) {
+        self.balance_mut(account, asset).available += amount;
+    }
+
+    pub fn withdraw(
+        &mut self,
+        account: AccountId,
+        asset: Asset,
+        amount: u128,
+    ) -> Result<(), OrderBookError> {
+        let balance = self.balance_mut(account, asset);
+        balance.available = balance
+            .available
+            .checked_sub(amount)
+            .ok_or(OrderBookError::InsufficientBalance)?;
+        Ok(())
+    }
+
+    pub(crate) fn lock(
+        &mut self,
+        account: AccountId,
+        asset: Asset,
+        amount: u128,
+    ) -> Result<(), OrderBookError> {
+        self.withdraw(account, asset, amount)?;
+        self.balance_mut(account, asset).locked += amount;
+        Ok(())
+    }
+
+    pub(crate) fn release(&mut self, account: AccountId, asset: Asset, amount: u128) {
+        let balance = self.balance_mut(account, asset);
+        balance.locked -= amount;
+        balance.available += amount;
+    }
+
+    // the buyer pays `cost` out of `consumed` locked quote, anything left over was locked
+    // at a worse limit price and goes back to available
+    pub(crate) fn settle(
+        &mut self,
+        buyer: AccountId,
+        seller: AccountId,
+        quantity: u128,
+        cost: u128,
+        consumed: u128,
+    ) {
+        let quote = self.balance_mut(buyer, Asset::Quote);
+        quote.locked -= consumed;
+        quote.available += consumed - cost;
+        self.balance_mut(buyer, Asset::Base).available += quantity;
+
+        self.balance_mut(seller, Asset::Base).locked -= quantity;
+        self.balance_mut(seller, Asset::Quote).available += cost;
+    }
+
+    fn balance_mut(&mut self, account: AccountId, asset: Asset) -> &mut Balance {
+        self.accounts.entry(account).or_default().balance_mut(asset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_release_and_settle() {
+        let mut ledger = Ledger::new();
+        ledger.deposit(1, Asset::Quote, 1000);
+        ledger.deposit(2, Asset::Base, 10);
+
+        assert_eq!(ledger.lock(1, Asset::Quote, 1001), Err(OrderBookError::InsufficientBalance));
+        ledger.lock(1, Asset::Quote, 600).unwrap();
+        ledger.lock(2, Asset::Base, 5).unwrap();
+
+        // 5 at 100 against a buy locked at 120
+        ledger.settle(1, 2, 5, 500, 600);
+        assert_eq!(ledger.account(1).quote, Balance { available: 500, locked: 0 });
+        assert_eq!(ledger.account(1).base, Balance { available: 5, locked: 0 });
+        assert_eq!(ledger.account(2).quote, Balance { available: 500, locked: 0 });
+        assert_eq!(ledger.account(2).base, Balance { available: 5, locked: 0 });
+
+        ledger.lock(2, Asset::Base, 5).unwrap();
+        ledger.release(2, Asset::Base, 5);
+        assert_eq!(ledger.account(2).base, Balance { available: 5, locked: 0 });
+        assert_eq!(ledger.withdraw(3, Asset::Base, 1), Err(OrderBookError::InsufficientBalance));
+    }
+}
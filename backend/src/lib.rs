@@ -1,56 +1,453 @@
-use std::fmt::Error;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
+pub mod bands;
+pub mod clock;
+pub mod error;
+pub mod events;
+pub mod exchange;
+pub mod feed;
+pub mod journal;
+pub mod ledger;
+pub mod matching;
 pub mod order_generator;
+pub mod price;
+pub mod rules;
+pub mod session;
+pub mod snapshot;
 // pub mod order_match;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use serde::{Deserialize, Serialize};
+use matching::Fifo;
+
+pub use bands::PriceBands;
+pub use clock::Clock;
+pub use error::{ExchangeError, OrderBookError};
+pub use events::{BookEvent, SequencedEvent};
+pub use exchange::{Exchange, Instrument};
+pub use feed::{Feed, FeedUpdate, TopOfBook};
+pub use journal::{Command, Journal, JournalEntry, Outcome};
+pub use ledger::{Account, Asset, Balance, Ledger};
+pub use matching::{Matching, MatchingPolicy};
+pub use price::Price;
+pub use rules::TradingRules;
+pub use session::{Schedule, SessionAction, SessionState};
+pub use snapshot::Snapshot;
+
+pub type AccountId = u64;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Side {
+    #[serde(rename = "buy")]
+    Bid,
+    #[serde(rename = "sell")]
+    Ask,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    #[default]
+    Limit,      // rests at its price if not filled
+    Market,     // sweeps the opposite side, never rests
+    StopMarket, // becomes a market order once the last trade reaches its trigger price
+    StopLimit,  // becomes a limit order once the last trade reaches its trigger price
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeInForce {
+    #[default]
+    Gtc,              // rests until cancelled
+    Ioc,              // whatever doesn't fill immediately is cancelled
+    Fok,              // fills completely on arrival or is rejected
+    Day,              // rests until the end of the day it was placed
+    Gtd(SystemTime),  // rests until the given time
+}
+
+// what happens to a post-only order that would cross the spread
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostOnlyMode {
+    #[default]
+    Reject,
+    Reprice, // moved to one tick behind the opposite best price
+}
 
-type Price = u64;
+// what happens when an order would trade against a resting order of the same account
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTradePrevention {
+    #[default]
+    Allow,              // they trade like any other pair
+    CancelNewest,       // the incoming order's remainder is cancelled, or it's rejected
+                        // with SelfTrade if it would meet its own order first
+    CancelOldest,       // the resting order is cancelled and matching carries on
+    CancelBoth,
+    DecrementAndCancel, // both shrink by the smaller quantity, whichever reaches 0 is cancelled
+}
 
 // simulate order flow
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
-    buy_order: bool, // refactor as enum
+    side: Side,
+    order_type: OrderType,
     price: Price,
     quantity: u128,
     id: u128, // change to str in future
     time_created: SystemTime,
+    priority: u64, // position in the queue at its price, lower goes first
+    time_in_force: TimeInForce,
+    expires_at: Option<SystemTime>, // DAY and GTD only
+    account: AccountId,
+    post_only: bool,
+    reduce_only: bool,
+    trigger_price: Option<Price>, // stop orders only
+    peak: Option<u128>,           // icebergs only, `quantity` is what is displayed
+    hidden: u128,                 // iceberg reserve not yet displayed
+    locked: u128,                 // funds still held in the book's ledger, if it has one
 }
 
+// what a client sends to OrderBook::submit
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct NewOrder {
+    pub side: Side,
+    #[serde(rename = "type", default)]
+    pub order_type: OrderType,
+    #[serde(default)]
+    pub price: Price, // ignored for market orders
+    pub quantity: u128,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    #[serde(default)]
+    pub account: AccountId,
+    #[serde(default)]
+    pub post_only: bool, // never takes liquidity
+    #[serde(default)]
+    pub reduce_only: bool, // never increases the account's position
+    #[serde(default)]
+    pub trigger_price: Option<Price>, // required for stop orders
+    #[serde(default)]
+    pub display_quantity: Option<u128>, // makes the order an iceberg with this peak
+}
+
+// execution report for one fill between an incoming (taker) and a resting (maker) order
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    pub trade_id: u128,
+    pub price: Price,
+    pub quantity: u128,
+    pub time: SystemTime,
+    pub aggressor_side: Side,
+    pub taker_order_id: u128,
+    pub maker_order_id: u128,
+    pub taker_remaining: u128, // quantity left on each order after this fill
+    pub maker_remaining: u128,
+}
+
+// execution report for a match that self-trade prevention stopped
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTradePrevented {
+    pub mode: SelfTradePrevention,
+    pub taker_order_id: u128,
+    pub maker_order_id: u128,
+    pub taker_cancelled: u128, // quantity taken off each order, which never trades
+    pub maker_cancelled: u128,
+}
+
+// where a call auction would uncross right now
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Uncross {
+    pub price: Price,
+    pub volume: u128,    // quantity that would trade at `price`
+    pub imbalance: i128, // buy minus sell quantity at `price`, left unmatched
+}
+
+// result of submitting an order
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct FillReport {
+    pub order_id: u128,
+    pub filled_quantity: u128,
+    pub average_price: Option<f64>, // None if nothing filled, or the notional overflows
+    pub unfilled_quantity: u128,    // rests on the book for limit orders, unless cancelled
+    pub fills: Vec<Transaction>,
+    pub self_trades: Vec<SelfTradePrevented>,
+}
+
+// one aggregated price level, only displayed quantity counts
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Level {
+    pub price: Price,
+    pub quantity: u128,
+    pub orders: usize,
+}
+
+// level 2 snapshot, bids best (highest) first and asks best (lowest) first
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Depth {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+// where a resting order lives, the slot in its level is found by binary searching priority
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+struct OrderLocation {
+    side: Side,
     price: Price,
-    quantity: u128,
-    time: SystemTime,
+    priority: u64,
 }
 
-// one asset
+// how many recent events a book keeps for `events_since`. the feed publishes after every
+// command, so only late readers of the event history ever miss any
+const EVENTS_KEPT: usize = 10_000;
+
+// how many of the latest trades a public view carries
+const PUBLIC_TRADES: usize = 50;
+
+// one asset. `P` shares each price level out among its resting orders, any
+// MatchingPolicy will do but only the Matching presets can be listed on an Exchange
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
-pub struct OrderBook {
+pub struct OrderBook<P = Matching> {
     total_orders: u128,                       // historic amount
     buy_orders: BTreeMap<Price, Vec<Order>>,  // refactor into Vec<Order>
     sell_orders: BTreeMap<Price, Vec<Order>>, // "        " f64 doesn't implement eq
     transactions: Vec<Transaction>,
+    total_trades: u128,
+    index: HashMap<u128, OrderLocation>, // resting orders only
+    next_priority: u64,
+    clock: Clock,
+    expiries: BTreeSet<(SystemTime, u128)>, // may hold ids that already left the book
+    post_only_mode: PostOnlyMode,
+    self_trade_prevention: SelfTradePrevention,
+    matching: P,   // how a level is shared among its resting orders
+    auction: bool, // orders rest without matching until the next uncross
+    indicative: Option<Uncross>, // kept up to date while in auction
+    session: SessionState,
+    schedule: Schedule, // empty unless sessions follow a timetable
+    bands: PriceBands,
+    reference_price: Option<Price>, // centre of the static band, the last uncross by default
+    resume_at: Option<SystemTime>,  // end of the current phase of a volatility interruption
+    #[serde(skip)] // history, not state. snapshots continue from `sequence`
+    events: Vec<SequencedEvent>, // level 3 feed, the most recent EVENTS_KEPT or more
+    sequence: u64,               // of the last event
+    self_trades: Option<Vec<SelfTradePrevented>>, // collected only while a submit runs
+    positions: HashMap<AccountId, i128>, // net filled quantity, long is positive
+    stop_buys: BTreeMap<Price, Vec<Order>>, // keyed on trigger price
+    stop_sells: BTreeMap<Price, Vec<Order>>,
+    stop_index: HashMap<u128, (Side, Price)>,
+    pending_stops: VecDeque<Order>, // triggered but not yet executed, in trigger order
+    rules: TradingRules,
+    ledger: Option<Ledger>, // balances aren't checked without one
+}
+
+impl Side {
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+}
+
+impl OrderType {
+    pub fn is_stop(self) -> bool {
+        matches!(self, OrderType::StopMarket | OrderType::StopLimit)
+    }
+
+    // what a stop order turns into once triggered
+    fn triggered(self) -> Self {
+        match self {
+            OrderType::StopMarket => OrderType::Market,
+            OrderType::StopLimit => OrderType::Limit,
+            other => other,
+        }
+    }
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Order {
     pub fn new() -> Self {
         Order {
-            buy_order: true,
-            price: 0,
+            side: Side::Bid,
+            order_type: OrderType::Limit,
+            price: Price::ZERO,
             quantity: 0,
             id: 0,
             time_created: SystemTime::now(),
+            priority: 0,
+            time_in_force: TimeInForce::Gtc,
+            expires_at: None,
+            account: 0,
+            post_only: false,
+            reduce_only: false,
+            trigger_price: None,
+            peak: None,
+            hidden: 0,
+            locked: 0,
+        }
+    }
+
+    pub fn id(&self) -> u128 {
+        self.id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn time_in_force(&self) -> TimeInForce {
+        self.time_in_force
+    }
+
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn trigger_price(&self) -> Option<Price> {
+        self.trigger_price
+    }
+
+    pub fn hidden_quantity(&self) -> u128 {
+        self.hidden
+    }
+
+    // copy that is safe to show anyone: only the displayed quantity, no reserve or lock
+    pub fn redacted(&self) -> Order {
+        Order {
+            hidden: 0,
+            peak: None,
+            locked: 0,
+            ..self.clone()
+        }
+    }
+
+    // whether this order is willing to trade against a resting order at `price`
+    fn crosses(&self, price: Price) -> bool {
+        match (self.order_type, self.side) {
+            (OrderType::Market | OrderType::StopMarket, _) => true,
+            (OrderType::Limit | OrderType::StopLimit, Side::Bid) => price <= self.price,
+            (OrderType::Limit | OrderType::StopLimit, Side::Ask) => price >= self.price,
+        }
+    }
+
+    // buy stops trigger when the market trades at or above them, sell stops at or below
+    fn is_triggered_by(&self, last_price: Price) -> bool {
+        match (self.side, self.trigger_price) {
+            (Side::Bid, Some(trigger)) => last_price >= trigger,
+            (Side::Ask, Some(trigger)) => last_price <= trigger,
+            (_, None) => true,
+        }
+    }
+}
+
+impl NewOrder {
+    pub fn limit(side: Side, price: impl Into<Price>, quantity: u128) -> Self {
+        NewOrder {
+            side,
+            order_type: OrderType::Limit,
+            price: price.into(),
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            account: 0,
+            post_only: false,
+            reduce_only: false,
+            trigger_price: None,
+            display_quantity: None,
+        }
+    }
+
+    pub fn market(side: Side, quantity: u128) -> Self {
+        NewOrder {
+            side,
+            order_type: OrderType::Market,
+            price: Price::ZERO,
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            account: 0,
+            post_only: false,
+            reduce_only: false,
+            trigger_price: None,
+            display_quantity: None,
+        }
+    }
+
+    pub fn stop_market(side: Side, trigger_price: impl Into<Price>, quantity: u128) -> Self {
+        NewOrder {
+            order_type: OrderType::StopMarket,
+            trigger_price: Some(trigger_price.into()),
+            ..NewOrder::market(side, quantity)
         }
     }
+
+    pub fn stop_limit(
+        side: Side,
+        trigger_price: impl Into<Price>,
+        price: impl Into<Price>,
+        quantity: u128,
+    ) -> Self {
+        NewOrder {
+            order_type: OrderType::StopLimit,
+            trigger_price: Some(trigger_price.into()),
+            ..NewOrder::limit(side, price, quantity)
+        }
+    }
+
+    // only `peak` is ever shown on the book, the rest is refilled from a hidden reserve
+    pub fn iceberg(mut self, peak: u128) -> Self {
+        self.display_quantity = Some(peak);
+        self
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn for_account(mut self, account: AccountId) -> Self {
+        self.account = account;
+        self
+    }
+
+    pub fn post_only(mut self) -> Self {
+        self.post_only = true;
+        self
+    }
+
+    pub fn reduce_only(mut self) -> Self {
+        self.reduce_only = true;
+        self
+    }
+}
+
+impl Default for Transaction {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Transaction {
     pub fn new() -> Self {
         Transaction {
-            price: 0,
+            trade_id: 0,
+            price: Price::ZERO,
             quantity: 0,
             time: SystemTime::now(),
+            aggressor_side: Side::Bid,
+            taker_order_id: 0,
+            maker_order_id: 0,
+            taker_remaining: 0,
+            maker_remaining: 0,
         }
     }
 }
@@ -58,465 +455,2968 @@ impl Transaction {
 // clear orders
 impl OrderBook {
     pub fn build() -> Self {
+        OrderBook::build_with_policy(Matching::Fifo)
+    }
+
+    pub fn build_with_rules(rules: TradingRules) -> Self {
+        OrderBook {
+            rules,
+            ..OrderBook::build()
+        }
+    }
+}
+
+impl<P: MatchingPolicy> OrderBook<P> {
+    pub fn build_with_policy(matching: P) -> Self {
         OrderBook {
             total_orders: 0,
             buy_orders: BTreeMap::new(),
             sell_orders: BTreeMap::new(),
             transactions: Vec::new(),
+            total_trades: 0,
+            index: HashMap::new(),
+            next_priority: 0,
+            clock: Clock::System,
+            expiries: BTreeSet::new(),
+            post_only_mode: PostOnlyMode::Reject,
+            self_trade_prevention: SelfTradePrevention::Allow,
+            matching,
+            auction: false,
+            indicative: None,
+            session: SessionState::Continuous,
+            schedule: Schedule::default(),
+            bands: PriceBands::default(),
+            reference_price: None,
+            resume_at: None,
+            events: Vec::new(),
+            sequence: 0,
+            self_trades: None,
+            positions: HashMap::new(),
+            stop_buys: BTreeMap::new(),
+            stop_sells: BTreeMap::new(),
+            stop_index: HashMap::new(),
+            pending_stops: VecDeque::new(),
+            rules: TradingRules::default(),
+            ledger: None,
         }
     }
 
-    pub fn buy(self: &mut Self, buy: bool, price: Price, quantity: u128) -> Result<u128, ()> {
-        if quantity == 0 {
-            println!("quantity can't be 0");
-            return Err(())
-        }
+    pub fn rules(&self) -> &TradingRules {
+        &self.rules
+    }
 
-        let id = self.total_orders;
-        let id2 = id.clone();
-        if buy {
-            self.buy_orders
-                .entry(price)
-                .or_insert_with(Vec::new)
-                .push(Order {
-                    buy_order: buy,
-                    price,
-                    quantity,
-                    id,
-                    time_created: SystemTime::now(),
-                });
-            self.total_orders += 1;
-        } else {
-            println!("not a buy order");
-            return Err(())
-        }
-        // resolve
-        self.resolve();
-        return Ok(id2)
+    pub fn set_post_only_mode(&mut self, mode: PostOnlyMode) {
+        self.post_only_mode = mode;
     }
 
-    pub fn sell(self: &mut Self, buy: bool, price: Price, quantity: u128) -> Result<u128, ()> {
-        if quantity == 0 {
-            println!("quantity can't be 0");
-            return Err(())
-        }
+    pub fn set_matching(&mut self, matching: P) {
+        self.matching = matching;
+    }
 
-        let id = self.total_orders;
-        let id2 = id.clone();
-        if !buy {
-            self.sell_orders
-                .entry(price)
-                .or_insert_with(Vec::new)
-                .push(Order {
-                    buy_order: buy,
-                    price,
-                    quantity,
-                    id,
-                    time_created: SystemTime::now(),
-                });
-            self.total_orders += 1;
-        } else {
-            println!("not a sell order");
-            return Err(())
-        }
-        // resolve
-        self.resolve();
-        return Ok(id2) // if order resolves this id is still returned
+    // the account is the owner, so orders of one account never trade with each other
+    // unless this is `Allow`
+    pub fn set_self_trade_prevention(&mut self, mode: SelfTradePrevention) {
+        self.self_trade_prevention = mode;
     }
 
-    pub fn market_buy(&self, quantity: u128) {}
+    // from now on every order must be paid for out of `ledger`
+    pub fn set_ledger(&mut self, ledger: Ledger) {
+        self.ledger = Some(ledger);
+    }
 
-    pub fn market_sell(&self,) {}
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
 
-    pub fn cancel(&mut self, id: u128) -> Result<Order, Error> {
-        let mut ord = Order::new();
-        let mut remove_sell = false;
-        let mut remove_buy = false;
+    pub fn ledger_mut(&mut self) -> Option<&mut Ledger> {
+        self.ledger.as_mut()
+    }
 
-        for (price, orders) in self.buy_orders.iter_mut() {
-            let mut index = 0;
-            if let Some(_) = orders.iter().find(|b| b.id == id) {
-                // remove from buy_orders
-                let order = orders.remove(index);
+    pub fn position(&self, account: AccountId) -> i128 {
+        self.positions.get(&account).copied().unwrap_or(0)
+    }
 
-                if orders.is_empty() {
-                    remove_buy = true;
-                }
+    pub fn best_bid(&self) -> Option<Price> {
+        self.buy_orders.last_key_value().map(|(price, _)| *price)
+    }
 
-                ord = order;
-            }
-            index += 1;
-        }
-        for (price, orders) in self.sell_orders.iter_mut() {
-            let mut index = 0;
-            if let Some(_) = orders.iter().find(|b| b.id == id) {
-                // remove from sell_orders
-                let order = orders.remove(index);
+    pub fn best_ask(&self) -> Option<Price> {
+        self.sell_orders.first_key_value().map(|(price, _)| *price)
+    }
 
-                if orders.is_empty() {
-                    remove_sell = true;
-                }
+    pub fn last_price(&self) -> Option<Price> {
+        self.transactions.last().map(|t| t.price)
+    }
 
-                ord = order;
-            }
-            index += 1;
-        }
+    // every trade so far, oldest first
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
 
-        if ord.quantity != 0 {
-            if remove_buy {
-                self.buy_orders.remove(&ord.price);
-            } else if remove_sell {
-                self.sell_orders.remove(&ord.price);
+    // run a journaled command with the clock stopped at `time`, so replaying the journal
+    // repeats every timestamp, expiry and schedule decision of the original run
+    pub fn apply(
+        &mut self,
+        time: SystemTime,
+        command: &Command,
+    ) -> Result<Outcome, OrderBookError> {
+        let clock = std::mem::replace(&mut self.clock, Clock::Manual(time));
+        let outcome = match command {
+            Command::Submit(order) => self.submit(order.clone()).map(Outcome::Submitted),
+            Command::Amend {
+                id,
+                price,
+                quantity,
+            } => self.amend(*id, *price, *quantity).map(Outcome::Amended),
+            Command::Cancel { id } => self.cancel(*id).map(Outcome::Cancelled),
+            Command::Transition(state) => self.transition(*state).map(Outcome::Transitioned),
+            Command::Tick => {
+                let expired = self.expire_orders();
+                let trades = self.run_schedule().unwrap_or_default();
+                Ok(Outcome::Ticked { expired, trades })
             }
-            return Ok(ord);
+        };
+        self.clock = clock;
+        outcome
+    }
+
+    // the checks a command has to pass whatever is on the book, against the book's rules
+    // only. one that fails them changes nothing, so it needn't be journaled
+    pub fn check(&self, command: &Command) -> Result<(), OrderBookError> {
+        match command {
+            Command::Submit(order) => self.check_new_order(order),
+            Command::Amend {
+                price, quantity, ..
+            } => self.check_amend(*price, *quantity),
+            Command::Cancel { .. } | Command::Transition(_) | Command::Tick => Ok(()),
         }
+    }
 
-        Err(Error)
+    fn check_new_order(&self, order: &NewOrder) -> Result<(), OrderBookError> {
+        if order.quantity == 0 {
+            return Err(OrderBookError::ZeroQuantity);
+        }
+        if order.order_type.is_stop() && order.trigger_price.is_none() {
+            return Err(OrderBookError::MissingTriggerPrice);
+        }
+        if order.display_quantity == Some(0) {
+            return Err(OrderBookError::ZeroQuantity);
+        }
+        self.rules.check_order(order)
     }
 
-    pub fn resolve(self: &mut Self) {
-        // need to buy called in buy/sell so the trades get resolved correctly by time they come in
-        // Keep resolving orders while there are matching prices
-        loop {
-            if self.buy_orders.is_empty() || self.sell_orders.is_empty() {
-                break;
-            }
+    fn check_amend(&self, price: Price, quantity: u128) -> Result<(), OrderBookError> {
+        if quantity == 0 {
+            return Err(OrderBookError::ZeroQuantity);
+        }
+        self.rules.check_amend(price, quantity)
+    }
 
-            let buy_price = if let Some((price, _)) = self.buy_orders.last_key_value() {
-                *price
-            } else {
-                break;
-            };
+    pub fn advance_clock(&mut self, by: Duration) {
+        self.clock.advance(by);
+    }
 
-            let sell_price = if let Some((price, _)) = self.sell_orders.first_key_value() {
-                *price
-            } else {
-                break;
-            };
+    // single entry point for every order type
+    pub fn submit(&mut self, order: NewOrder) -> Result<FillReport, OrderBookError> {
+        self.check_new_order(&order)?;
+        self.check_session(SessionAction::Submit)?;
+        if matches!(order.order_type, OrderType::Limit | OrderType::StopLimit) {
+            self.check_static_band(order.price)?;
+        }
+        let immediate = matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok);
+        if self.auction && (order.order_type == OrderType::Market || immediate) {
+            return Err(OrderBookError::NotAllowedInAuction);
+        }
 
-            if buy_price < sell_price {
-                break;
-            }
+        // nothing that has already expired may trade with the new order
+        self.expire_orders();
 
-            let mut should_remove_buy_price = false;
-            let mut should_remove_sell_price = false;
-            let mut match_quantity = 0;
+        let now = self.now();
+        let expires_at = match order.time_in_force {
+            TimeInForce::Day => Some(clock::end_of_day(now)),
+            TimeInForce::Gtd(time) if time <= now => return Err(OrderBookError::ExpiryInPast),
+            TimeInForce::Gtd(time) => Some(time),
+            _ => None,
+        };
 
-            // First, get the order details without mutable borrows
-            if let Some(buy_orders) = self.buy_orders.get(&buy_price) {
-                if let Some(sell_orders) = self.sell_orders.get(&sell_price) {
-                    if !buy_orders.is_empty() && !sell_orders.is_empty() {
-                        let buy_qty = buy_orders[0].quantity;
-                        let sell_qty = sell_orders[0].quantity;
-                        match_quantity = std::cmp::min(buy_qty, sell_qty);
-                    }
-                }
-            }
+        let mut incoming = Order {
+            side: order.side,
+            order_type: order.order_type,
+            price: order.price,
+            quantity: order.quantity,
+            id: self.total_orders,
+            time_created: now,
+            priority: 0, // assigned if it rests
+            time_in_force: order.time_in_force,
+            expires_at,
+            account: order.account,
+            post_only: order.post_only,
+            reduce_only: order.reduce_only,
+            trigger_price: order.trigger_price,
+            peak: order.display_quantity,
+            hidden: 0, // the whole quantity is aggressive, it's split if it rests
+            locked: 0,
+        };
 
-            if match_quantity > 0 {
-                // Now process the orders with separate mutable borrows
-                if let Some(buy_orders) = self.buy_orders.get_mut(&buy_price) {
-                    if let Some(sell_orders) = self.sell_orders.get_mut(&sell_price) {
-                        if !buy_orders.is_empty() && !sell_orders.is_empty() {
-                            // Update buy order
-                            let buy_order = &mut buy_orders[0];
-                            buy_order.quantity -= match_quantity;
-                            if buy_order.quantity == 0 {
-                                buy_orders.remove(0);
-                                should_remove_buy_price = buy_orders.is_empty();
-                            }
-
-                            // Update sell order
-                            let sell_order = &mut sell_orders[0];
-                            sell_order.quantity -= match_quantity;
-                            if sell_order.quantity == 0 {
-                                sell_orders.remove(0);
-                                should_remove_sell_price = sell_orders.is_empty();
-                            }
-
-                            // Create transaction
-                            let transaction = Transaction {
-                                price: sell_price, // Use sell price as the match price --> aggro sell uses buy_price?
-                                quantity: match_quantity,
-                                time: SystemTime::now(),
-                            };
-                            self.transactions.push(transaction);
-                        }
-                    }
-                }
+        // stops are checked as takers when they trigger, not now, but are paid for now
+        if !incoming.order_type.is_stop() {
+            self.check_taker(&mut incoming)?;
+        }
+        self.lock(&mut incoming)?;
+        let quantity = incoming.quantity;
 
-                // Remove empty price levels
-                if should_remove_buy_price {
-                    self.buy_orders.remove(&buy_price);
-                }
-                if should_remove_sell_price {
-                    self.sell_orders.remove(&sell_price);
-                }
-            } else {
-                // No more matches possible
-                break;
+        let id = self.total_orders;
+        self.total_orders += 1;
+
+        let first_trade = self.transactions.len();
+        self.self_trades = Some(Vec::new());
+        if incoming.order_type.is_stop() {
+            match self.last_price() {
+                Some(last) if incoming.is_triggered_by(last) => self.pending_stops.push_back(incoming),
+                _ => self.park(incoming),
             }
+        } else {
+            self.execute(incoming);
         }
+        self.run_triggered_stops();
+
+        // triggered stops may have traded against this order too
+        let fills = self.transactions[first_trade..]
+            .iter()
+            .filter(|t| t.taker_order_id == id || t.maker_order_id == id)
+            .cloned()
+            .collect();
+        let self_trades = self
+            .self_trades
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|p| p.taker_order_id == id || p.maker_order_id == id)
+            .collect();
+        self.refresh_indicative();
+        Ok(FillReport::from_fills(id, quantity, fills, self_trades))
     }
 
-    pub fn display(&self) {
-        println!("Order Book Stats");
-        println!("-------------------");
-        println!("bids");
-        for (_, order) in self.buy_orders.iter() {
-            for ord in order {
-                println!("Bid: price: {}, quantity: {}", ord.price, ord.quantity);
-            }
+    // post-only, reduce-only and fill-or-kill checks for an order about to match
+    fn check_taker(&self, order: &mut Order) -> Result<(), OrderBookError> {
+        if order.post_only {
+            self.apply_post_only(order)?;
         }
-        println!("-------------------");
-        println!("asks");
-        for (_, order) in self.sell_orders.iter() {
-            for ord in order {
-                println!("Ask: price: {}, quantity: {}", ord.price, ord.quantity);
+        if order.reduce_only {
+            // clipped so a fill can never flip the position
+            let reducible = reducible(&self.positions, order.account, order.side);
+            if reducible == 0 {
+                return Err(OrderBookError::ReduceOnlyWouldIncrease);
             }
+            order.quantity = std::cmp::min(order.quantity, reducible);
         }
-        println!("-------------------");
-        println!("transactions");
-        for transaction in self.transactions.iter() {
-            println!(
-                "Transaction: price: {}, quantity: {}, time: {:?}",
-                transaction.price, transaction.quantity, transaction.time
-            );
+        // an order cancel newest would cancel before it fills anything is rejected whole
+        if self.self_trade_prevention == SelfTradePrevention::CancelNewest
+            && !self.auction
+            && self.crossing_orders(order).next().is_some_and(|m| m.account == order.account)
+        {
+            return Err(OrderBookError::SelfTrade);
+        }
+        // fill or kill is rejected before it gets an id or touches the book
+        if order.time_in_force == TimeInForce::Fok && self.fillable(order) < order.quantity {
+            return Err(OrderBookError::InsufficientLiquidity);
         }
+        Ok(())
     }
 
-    pub fn display_depth_chart(&self) {}
+    // match, then rest whatever is left if the order type allows it
+    fn execute(&mut self, mut order: Order) {
+        let mut interrupted = false;
+        if !self.auction {
+            self.resolve(&mut order, None);
+            interrupted = self.auction;
+        }
 
-    pub fn get_buy_order(&self, id: u128) -> Result<&Order, Error> {
-        for (_, orders) in self.buy_orders.iter() {
-            if let Some(ord) = orders.iter().find(|b| b.id == id) {
-                return Ok(ord);
+        // market orders never rest, and neither do IOC/FOK remainders
+        let rests = order.order_type == OrderType::Limit
+            && !matches!(order.time_in_force, TimeInForce::Ioc | TimeInForce::Fok);
+        if rests && order.quantity > 0 {
+            self.rest(order);
+        } else {
+            self.release(&order);
+            // cut off by a volatility interruption, a triggered stop has no one to tell
+            if interrupted && order.quantity > 0 {
+                self.emit(BookEvent::OrderRejected {
+                    order_id: order.id,
+                    account: order.account,
+                    reason: OrderBookError::BookHalted,
+                });
             }
         }
-        Err(Error)
     }
 
-    pub fn get_mut_buy_order(self: &mut Self, id: u128) -> Result<&mut Order, Error> {
-        for (_, orders) in self.buy_orders.iter_mut() {
-            if let Some(ord) = orders.iter_mut().find(|b| b.id == id) {
-                return Ok(ord);
+    // hold what `order` could cost in the ledger. a stop market buy can't know what it
+    // will sweep, so it holds the notional at its trigger until it triggers
+    fn lock(&mut self, order: &mut Order) -> Result<(), OrderBookError> {
+        if self.ledger.is_none() {
+            return Ok(());
+        }
+        let quantity = order.quantity + order.hidden;
+        let (asset, amount) = match (order.side, order.order_type) {
+            (Side::Ask, _) => (Asset::Base, quantity),
+            (Side::Bid, OrderType::Market) => (Asset::Quote, self.sweep_cost(order)),
+            (Side::Bid, OrderType::StopMarket) => {
+                let trigger = order.trigger_price.expect("stop orders always have a trigger price");
+                (Asset::Quote, trigger.checked_notional(quantity).unwrap_or(u128::MAX))
             }
+            // an overflowing notional can never be paid for
+            (Side::Bid, _) => {
+                let notional = order.price.checked_notional(quantity);
+                (Asset::Quote, notional.unwrap_or(u128::MAX))
+            }
+        };
+        if let Some(ledger) = &mut self.ledger {
+            ledger.lock(order.account, asset, amount)?;
         }
-        Err(Error)
+        order.locked = amount;
+        Ok(())
     }
 
-    pub fn get_sell_order(&self, id: u128) -> Result<&Order, Error> {
-        for (_, orders) in self.sell_orders.iter() {
-            if let Some(ord) = orders.iter().find(|b| b.id == id) {
-                return Ok(ord);
-            }
+    // give back whatever `order` still holds
+    fn release(&mut self, order: &Order) {
+        if let Some(ledger) = &mut self.ledger
+            && order.locked > 0
+        {
+            ledger.release(order.account, asset_locked_by(order.side), order.locked);
         }
-        Err(Error)
     }
 
-    pub fn get_mut_sell_order(self: &mut Self, id: u128) -> Result<&mut Order, Error> {
-        for (_, orders) in self.sell_orders.iter_mut() {
-            if let Some(ord) = orders.iter_mut().find(|b| b.id == id) {
-                return Ok(ord);
-            }
+    // wait in the trigger book until the last trade reaches the trigger price
+    fn park(&mut self, order: Order) {
+        let trigger = order.trigger_price.expect("stop orders always have a trigger price");
+        if let Some(expires_at) = order.expires_at {
+            self.expiries.insert((expires_at, order.id));
         }
-        Err(Error)
+        self.stop_index.insert(order.id, (order.side, trigger));
+        let stops = match order.side {
+            Side::Bid => &mut self.stop_buys,
+            Side::Ask => &mut self.stop_sells,
+        };
+        stops.entry(trigger).or_default().push(order);
     }
 
-    pub fn get_buy_order_quantity(&self, id: u128) -> Result<u128, Error> {
-        for (_, orders) in self.buy_orders.iter() {
-            if let Some(ord) = orders.iter().find(|b| b.id == id) {
-                return Ok(ord.quantity);
+    // move every stop the trade at `last_price` triggers onto the pending queue,
+    // nearest trigger first then FIFO
+    fn trigger_stops(&mut self, last_price: Price) {
+        while let Some(level) = self.stop_buys.first_entry() {
+            if *level.key() > last_price {
+                break;
+            }
+            for stop in level.remove() {
+                self.stop_index.remove(&stop.id);
+                self.pending_stops.push_back(stop);
             }
         }
-        Err(Error)
-    }
-
-    pub fn get_sell_order_quantity(&self, id: u128) -> Result<u128, Error> {
-        for (_, orders) in self.sell_orders.iter() {
-            if let Some(ord) = orders.iter().find(|b| b.id == id) {
-                return Ok(ord.quantity);
+        while let Some(level) = self.stop_sells.last_entry() {
+            if *level.key() < last_price {
+                break;
+            }
+            for stop in level.remove() {
+                self.stop_index.remove(&stop.id);
+                self.pending_stops.push_back(stop);
             }
         }
-        Err(Error)
     }
 
-    pub fn get_tot_orders(&self) -> &u128 {
-        &self.total_orders
+    // run triggered stops one at a time, their trades can trigger more stops behind them
+    // a volatility interruption can start an auction partway through, the rest then wait
+    // for continuous trading, as market orders can't rest
+    fn run_triggered_stops(&mut self) {
+        while !self.auction
+            && let Some(mut stop) = self.pending_stops.pop_front()
+        {
+            stop.order_type = stop.order_type.triggered();
+            // held again for what the triggered order costs now, which for a market buy
+            // can be more than at submission
+            self.release(&stop);
+            stop.locked = 0;
+            let checked = self.check_taker(&mut stop).and_then(|_| self.lock(&mut stop));
+            match checked {
+                Ok(()) => self.execute(stop),
+                Err(reason) => self.emit(BookEvent::OrderRejected {
+                    order_id: stop.id,
+                    account: stop.account,
+                    reason,
+                }),
+            }
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_build_order_book() {
-        let a = OrderBook::build();
-        let b = OrderBook {
-            total_orders: 0,
-            buy_orders: BTreeMap::new(),
-            sell_orders: BTreeMap::new(),
-            transactions: Vec::new(),
+    // reject or reprice a post-only order that would take liquidity
+    fn apply_post_only(&self, order: &mut Order) -> Result<(), OrderBookError> {
+        if order.order_type != OrderType::Limit {
+            return Err(OrderBookError::PostOnlyWouldCross);
+        }
+        let best = match order.side {
+            Side::Bid => self.best_ask(),
+            Side::Ask => self.best_bid(),
+        };
+        let Some(best) = best.filter(|best| order.crosses(*best)) else {
+            return Ok(());
         };
 
-        assert_eq!(a, b);
+        match self.post_only_mode {
+            PostOnlyMode::Reject => Err(OrderBookError::PostOnlyWouldCross),
+            PostOnlyMode::Reprice => {
+                let tick = self.rules.tick_size;
+                order.price = match order.side {
+                    Side::Bid => best.checked_sub(tick).ok_or(OrderBookError::PostOnlyWouldCross)?,
+                    Side::Ask => best.checked_add(tick).ok_or(OrderBookError::PostOnlyWouldCross)?,
+                };
+                Ok(())
+            }
+        }
     }
 
-    #[test]
-    fn test_buy() {
-        let mut a = OrderBook::build();
-        a.buy(true, 2, 1);
+    pub fn buy(
+        &mut self,
+        price: impl Into<Price>,
+        quantity: u128,
+    ) -> Result<FillReport, OrderBookError> {
+        self.submit(NewOrder::limit(Side::Bid, price, quantity))
+    }
 
-        assert_eq!(a.buy_orders.len(), 1);
-        assert_eq!(a.total_orders, 1);
+    pub fn sell(
+        &mut self,
+        price: impl Into<Price>,
+        quantity: u128,
+    ) -> Result<FillReport, OrderBookError> {
+        self.submit(NewOrder::limit(Side::Ask, price, quantity)) // if order resolves the id is still reported
     }
 
-    #[test]
-    fn test_sell() {
-        let mut a = OrderBook::build();
-        a.sell(false, 2, 1);
+    // buy `quantity` from the best asks outward, whatever is left is not rested
+    pub fn market_buy(&mut self, quantity: u128) -> Result<FillReport, OrderBookError> {
+        self.submit(NewOrder::market(Side::Bid, quantity))
+    }
 
-        assert_eq!(a.sell_orders.len(), 1);
-        assert_eq!(a.total_orders, 1);
+    // sell `quantity` into the best bids outward, whatever is left is not rested
+    pub fn market_sell(&mut self, quantity: u128) -> Result<FillReport, OrderBookError> {
+        self.submit(NewOrder::market(Side::Ask, quantity))
     }
 
-    #[test]
-    fn test_cancel() {
-        let mut a = OrderBook::build();
-        a.buy(true, 2, 1);
+    // append to the back of the queue at its price
+    fn rest(&mut self, mut order: Order) {
+        if let Some(peak) = order.peak
+            && order.quantity > peak
+        {
+            order.hidden = order.quantity - peak;
+            order.quantity = peak;
+        }
+        order.priority = self.next_priority;
+        self.next_priority += 1;
+        if let Some(expires_at) = order.expires_at {
+            self.expiries.insert((expires_at, order.id));
+        }
+        self.index.insert(
+            order.id,
+            OrderLocation {
+                side: order.side,
+                price: order.price,
+                priority: order.priority,
+            },
+        );
+        self.emit(BookEvent::OrderAdded {
+            order_id: order.id,
+            account: order.account,
+            side: order.side,
+            price: order.price,
+            quantity: order.quantity,
+        });
+        self.book_mut(order.side).entry(order.price).or_default().push(order);
+    }
 
-        let b = a.cancel(0);
-        a.display();
-        println!("{}", a.buy_orders.len());
+    // change a resting order's price and/or total quantity, keeping its id.
+    // shrinking in place keeps time priority, a new price or a bigger quantity goes to the
+    // back of the queue and may trade if the new price crosses
+    pub fn amend(
+        &mut self,
+        id: u128,
+        new_price: impl Into<Price>,
+        new_quantity: u128,
+    ) -> Result<Order, OrderBookError> {
+        let new_price = new_price.into();
+        self.check_amend(new_price, new_quantity)?;
+        self.check_session(SessionAction::Amend)?;
+        let side = self.index.get(&id).ok_or(OrderBookError::UnknownOrderId(id))?.side;
+        let current = self.get_order(id, side)?;
+        if new_price == current.price && new_quantity == current.quantity + current.hidden {
+            return Ok(current.clone()); // nothing to change
+        }
 
-        assert_eq!(a.buy_orders.len(), 0);
-    }
+        if new_price == current.price && new_quantity <= current.quantity + current.hidden {
+            let total = current.quantity + current.hidden;
+            let ord = self.get_mut_order(id, side)?;
+            // the reserve shrinks before the displayed quantity does
+            ord.hidden = new_quantity.saturating_sub(ord.quantity);
+            ord.quantity = std::cmp::min(ord.quantity, new_quantity);
+            // the lock is proportional to the quantity at a fixed price
+            let freed = ord.locked / total * (total - new_quantity);
+            ord.locked -= freed;
+            let ord = ord.clone();
+            if let Some(ledger) = &mut self.ledger {
+                ledger.release(ord.account, asset_locked_by(side), freed);
+            }
+            self.emit(BookEvent::OrderReduced {
+                order_id: id,
+                account: ord.account,
+                remaining: ord.quantity,
+            });
+            self.refresh_indicative();
+            return Ok(ord);
+        }
 
-    #[test]
+        // only now, a reduction keeps a price that may have left the band since
+        self.check_static_band(new_price)?;
+        let mut replacement = current.clone();
+        replacement.price = new_price;
+        replacement.quantity = new_quantity;
+        replacement.hidden = 0; // split again if it rests
+        replacement.locked = 0;
+        // checked like a new order before the original is touched, so a rejection
+        // changes nothing and a reduce-only replacement is clipped to the position
+        self.check_taker(&mut replacement)?;
+        let new_quantity = replacement.quantity;
+        if let Some(ledger) = &self.ledger {
+            let available = ledger.account(current.account);
+            let available = match side {
+                Side::Bid => available.quote.available,
+                Side::Ask => available.base.available,
+            };
+            let needed = match side {
+                Side::Bid => replacement.price.checked_notional(new_quantity).unwrap_or(u128::MAX),
+                Side::Ask => new_quantity,
+            };
+            if needed > available + current.locked {
+                return Err(OrderBookError::InsufficientBalance);
+            }
+        }
+
+        self.cancel(id)?;
+        self.lock(&mut replacement)?;
+        self.execute(replacement.clone());
+        self.run_triggered_stops();
+        self.refresh_indicative();
+
+        match self.get_order(id, side) {
+            Ok(ord) => Ok(ord.clone()),
+            Err(_) => {
+                // filled completely at the new price
+                replacement.quantity = 0;
+                Ok(replacement)
+            }
+        }
+    }
+
+    pub fn cancel(&mut self, id: u128) -> Result<Order, OrderBookError> {
+        self.check_session(SessionAction::Cancel)?;
+        self.cancel_order(id)
+    }
+
+    fn cancel_order(&mut self, id: u128) -> Result<Order, OrderBookError> {
+        if self.stop_index.contains_key(&id) {
+            return Ok(self.cancel_stop(id));
+        }
+        if let Some(slot) = self.pending_stops.iter().position(|o| o.id == id) {
+            let ord = self.pending_stops.remove(slot).expect("found above");
+            self.release(&ord);
+            return Ok(ord);
+        }
+        let ord = self.take(id)?;
+        self.release(&ord);
+        self.emit(BookEvent::OrderCancelled {
+            order_id: id,
+            account: ord.account,
+        });
+        self.refresh_indicative();
+        Ok(ord)
+    }
+
+    // remove a resting order from the book, it keeps whatever it has locked
+    fn take(&mut self, id: u128) -> Result<Order, OrderBookError> {
+        let loc = *self.index.get(&id).ok_or(OrderBookError::UnknownOrderId(id))?;
+        let book = self.book_mut(loc.side);
+        let orders = book.get_mut(&loc.price).expect("index points at a missing level");
+        let slot = Self::slot(orders, loc.priority).expect("index points at a missing order");
+
+        let ord = orders.remove(slot);
+        if orders.is_empty() {
+            book.remove(&loc.price);
+        }
+        self.index.remove(&id);
+        Ok(ord)
+    }
+
+    // put an order taken out of the book back in its old place in the queue
+    fn reinstate(&mut self, mut order: Order) {
+        if let Some(peak) = order.peak
+            && order.quantity > peak
+        {
+            order.hidden = order.quantity - peak;
+            order.quantity = peak;
+        }
+        self.index.insert(
+            order.id,
+            OrderLocation {
+                side: order.side,
+                price: order.price,
+                priority: order.priority,
+            },
+        );
+        let orders = self.book_mut(order.side).entry(order.price).or_default();
+        let slot = orders.partition_point(|o| o.priority < order.priority);
+        orders.insert(slot, order);
+    }
+
+    fn cancel_stop(&mut self, id: u128) -> Order {
+        let (side, trigger) = self.stop_index.remove(&id).expect("checked by cancel");
+        let stops = match side {
+            Side::Bid => &mut self.stop_buys,
+            Side::Ask => &mut self.stop_sells,
+        };
+        let orders = stops.get_mut(&trigger).expect("stop index points at a missing level");
+        let slot = orders.iter().position(|o| o.id == id).expect("stop index points at a missing order");
+        let ord = orders.remove(slot);
+        if orders.is_empty() {
+            stops.remove(&trigger);
+        }
+        self.release(&ord);
+        ord
+    }
+
+    // cancel every DAY/GTD order whose expiry has passed on the book's clock
+    pub fn expire_orders(&mut self) -> Vec<Order> {
+        let now = self.now();
+        let mut expired = Vec::new();
+        while let Some(&(expires_at, id)) = self.expiries.first() {
+            if expires_at > now {
+                break;
+            }
+            self.expiries.pop_first();
+            // filled or cancelled orders are left in expiries, so skip those
+            if let Ok(order) = self.cancel_order(id) {
+                expired.push(order);
+            }
+        }
+        expired
+    }
+
+    pub fn session(&self) -> SessionState {
+        self.session
+    }
+
+    // move to `next`, running the uncross if that ends an auction. every state other
+    // than Continuous collects orders without matching them
+    pub fn transition(&mut self, next: SessionState) -> Result<Vec<Transaction>, OrderBookError> {
+        let from = self.session;
+        if next == from {
+            return Ok(Vec::new());
+        }
+        if !from.can_transition_to(next) {
+            return Err(OrderBookError::InvalidTransition { from, to: next });
+        }
+        self.session = next;
+        self.resume_at = None; // a manual transition ends any volatility interruption
+        let trades = if from.uncrosses_into(next) {
+            self.uncross()
+        } else {
+            Vec::new()
+        };
+        if next != SessionState::Continuous && !self.auction {
+            self.start_auction();
+        }
+        Ok(trades)
+    }
+
+    // from now on `run_schedule` moves the session along this timetable
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+
+    // move to whatever state the schedule has for the book's clock, and along a
+    // volatility interruption once its time is up. a halt is only lifted by hand or by
+    // the end of the interruption, but a halted book still closes on time
+    pub fn run_schedule(&mut self) -> Result<Vec<Transaction>, OrderBookError> {
+        let Some(next) = self.scheduled_transition() else {
+            return Ok(Vec::new());
+        };
+        let reopening = self.session == SessionState::Halted && self.resume_at.is_some();
+        let trades = self.transition(next)?;
+        if reopening && next == SessionState::OpeningAuction {
+            self.resume_at = Some(self.now() + self.bands.halt_duration);
+        }
+        Ok(trades)
+    }
+
+    // whether an expiry or a session change is due, i.e. whether `expire_orders` or
+    // `run_schedule` would change anything right now
+    pub fn timers_due(&self) -> bool {
+        let expiry_due = self.expiries.first().is_some_and(|(at, _)| *at <= self.now());
+        expiry_due || self.scheduled_transition().is_some()
+    }
+
+    // where `run_schedule` would move the session right now
+    fn scheduled_transition(&self) -> Option<SessionState> {
+        if let Some(resume_at) = self.resume_at
+            && self.now() >= resume_at
+        {
+            return Some(match self.session {
+                SessionState::Halted => SessionState::OpeningAuction,
+                _ => SessionState::Continuous,
+            });
+        }
+        let target = self.schedule.state_at(self.now())?;
+        let interrupted = self.session == SessionState::Halted || self.resume_at.is_some();
+        if target == self.session || (interrupted && target != SessionState::Closed) {
+            return None;
+        }
+        // a timetable the book can't follow from here is ignored rather than retried
+        self.session.can_transition_to(target).then_some(target)
+    }
+
+    pub fn set_price_bands(&mut self, bands: PriceBands) {
+        self.bands = bands;
+    }
+
+    pub fn price_bands(&self) -> &PriceBands {
+        &self.bands
+    }
+
+    // e.g. the previous close. every uncross moves it to the uncross price
+    pub fn set_reference_price(&mut self, price: impl Into<Price>) {
+        self.reference_price = Some(price.into());
+    }
+
+    // falls back to the last trade when no reference price was set
+    pub fn reference_price(&self) -> Option<Price> {
+        self.reference_price.or(self.last_price())
+    }
+
+    // a halt ends with a reopening auction, each lasts the band's halt_duration
+    pub fn halted_until(&self) -> Option<SystemTime> {
+        self.resume_at
+    }
+
+    fn check_static_band(&self, price: Price) -> Result<(), OrderBookError> {
+        match self.reference_price() {
+            Some(reference) if !self.bands.within_static(reference, price) => {
+                Err(OrderBookError::PriceOutOfBand(price))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn within_dynamic_band(&self, last_price: Option<Price>, price: Price) -> bool {
+        last_price.is_none_or(|last| self.bands.within_dynamic(last, price))
+    }
+
+    // a trade would print outside the dynamic band: halt instead, then reopen through
+    // an auction so the price is found from the orders collected meanwhile
+    fn interrupt(&mut self) {
+        self.session = SessionState::Halted;
+        self.resume_at = Some(self.now() + self.bands.halt_duration);
+        if !self.auction {
+            self.start_auction();
+        }
+    }
+
+    fn check_session(&self, action: SessionAction) -> Result<(), OrderBookError> {
+        match self.session {
+            state if state.allows(action) => Ok(()),
+            SessionState::Halted => Err(OrderBookError::BookHalted),
+            state => Err(OrderBookError::NotAllowedInSession(state)),
+        }
+    }
+
+    // stop matching, orders accumulate on the book until `uncross`
+    pub fn start_auction(&mut self) {
+        self.auction = true;
+        self.refresh_indicative();
+    }
+
+    pub fn in_auction(&self) -> bool {
+        self.auction
+    }
+
+    // where the auction would uncross if it ended now, None if nothing crosses
+    pub fn indicative(&self) -> Option<Uncross> {
+        self.indicative
+    }
+
+    // end the auction: every crossable order trades at the single indicative price, buys
+    // in price-time priority against sells through the matching policy. whatever is
+    // left keeps its place in the queue and continuous trading resumes.
+    // self-trade prevention can cancel orders the indicative price counted on, leaving
+    // the book crossed, so the point is worked out again until nothing crosses
+    pub fn uncross(&mut self) -> Vec<Transaction> {
+        let first_trade = self.transactions.len();
+        let mut uncrossed_at = None;
+        while let Some(Uncross { price, .. }) = self.uncross_point() {
+            uncrossed_at = Some(price);
+            while let Some(bid) = self.best_bid().filter(|bid| *bid >= price) {
+                if self.best_ask().is_none_or(|ask| ask > price) {
+                    break;
+                }
+                let id = self.buy_orders[&bid][0].id;
+                let mut taker = self.take(id).expect("best bid is in the index");
+                // the whole order takes part, not only its displayed peak
+                taker.quantity += taker.hidden;
+                taker.hidden = 0;
+                self.resolve(&mut taker, Some(price));
+                if taker.quantity > 0 {
+                    self.reinstate(taker);
+                    break; // the sells at or below the price are used up
+                }
+                self.release(&taker);
+            }
+        }
+        if uncrossed_at.is_some() {
+            self.reference_price = uncrossed_at;
+        }
+        self.auction = false;
+        self.indicative = None;
+        if self.session == SessionState::Continuous {
+            self.run_triggered_stops();
+        }
+        self.transactions[first_trade..].to_vec()
+    }
+
+    fn refresh_indicative(&mut self) {
+        if self.auction {
+            self.indicative = self.uncross_point();
+        }
+    }
+
+    // the price that trades the most volume. ties go to the smallest imbalance, then to
+    // the side of the surplus (highest price if every candidate has more buys, lowest
+    // if more sells), then to the price nearest the last trade, then to the lower price
+    fn uncross_point(&self) -> Option<Uncross> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        if bid < ask {
+            return None;
+        }
+        let total = |orders: &Vec<Order>| -> u128 {
+            orders.iter().map(|o| o.quantity + o.hidden).sum()
+        };
+        let candidates: BTreeSet<Price> = self
+            .buy_orders
+            .range(ask..=bid)
+            .chain(self.sell_orders.range(ask..=bid))
+            .map(|(price, _)| *price)
+            .collect();
+        let points: Vec<Uncross> = candidates
+            .into_iter()
+            .map(|price| {
+                let buys: u128 = self.buy_orders.range(price..).map(|(_, o)| total(o)).sum();
+                let sells: u128 = self.sell_orders.range(..=price).map(|(_, o)| total(o)).sum();
+                Uncross {
+                    price,
+                    volume: std::cmp::min(buys, sells),
+                    imbalance: buys as i128 - sells as i128,
+                }
+            })
+            .collect();
+
+        let volume = points.iter().map(|p| p.volume).max()?;
+        let points: Vec<Uncross> = points.into_iter().filter(|p| p.volume == volume).collect();
+        let smallest = points.iter().map(|p| p.imbalance.unsigned_abs()).min()?;
+        let points: Vec<Uncross> = points
+            .into_iter()
+            .filter(|p| p.imbalance.unsigned_abs() == smallest)
+            .collect();
+        if points.iter().all(|p| p.imbalance > 0) {
+            return points.last().copied();
+        }
+        if points.iter().all(|p| p.imbalance < 0) {
+            return points.first().copied();
+        }
+        match self.reference_price() {
+            Some(reference) => points
+                .into_iter()
+                .min_by_key(|p| p.price.units().abs_diff(reference.units())),
+            None => points.first().copied(),
+        }
+    }
+
+    // the resting orders `taker` would trade against, in matching order
+    fn crossing_orders<'a>(&'a self, taker: &'a Order) -> impl Iterator<Item = &'a Order> + 'a {
+        let opposite = self.book(taker.side.opposite());
+        let levels: Box<dyn Iterator<Item = (&Price, &Vec<Order>)>> = match taker.side {
+            Side::Bid => Box::new(opposite.iter()),
+            Side::Ask => Box::new(opposite.iter().rev()),
+        };
+        let last_price = self.last_price();
+        levels
+            .take_while(move |(price, _)| {
+                taker.crosses(**price) && self.within_dynamic_band(last_price, **price)
+            })
+            .flat_map(|(_, orders)| orders.iter())
+    }
+
+    // the crossing orders with how much of each could trade. a reduce-only one gets what
+    // is left of its account's position after the reduce-only orders ahead of it
+    fn tradable<'a>(&'a self, taker: &'a Order) -> impl Iterator<Item = (&'a Order, u128)> + 'a {
+        let mut reduced: HashMap<AccountId, u128> = HashMap::new();
+        self.crossing_orders(taker).map(move |maker| {
+            let mut size = maker.quantity + maker.hidden;
+            if maker.reduce_only {
+                let used = reduced.entry(maker.account).or_default();
+                let left = reducible(&self.positions, maker.account, maker.side) - *used;
+                size = std::cmp::min(size, left);
+                *used += size;
+            }
+            (maker, size)
+        })
+    }
+
+    // how much of `taker` the opposite side could fill right now
+    fn fillable(&self, taker: &Order) -> u128 {
+        let mut available = 0;
+        for (maker, size) in self.tradable(taker) {
+            if available >= taker.quantity {
+                break;
+            }
+            if maker.account == taker.account {
+                match self.self_trade_prevention {
+                    SelfTradePrevention::Allow => {}
+                    SelfTradePrevention::CancelOldest => continue,
+                    // every other mode stops the taker filling completely here
+                    _ => break,
+                }
+            }
+            available += size;
+        }
+        available
+    }
+
+    // what filling as much of `taker` as the opposite side allows would cost right now
+    fn sweep_cost(&self, taker: &Order) -> u128 {
+        let mut remaining = taker.quantity;
+        let mut cost: u128 = 0;
+        for (maker, size) in self.tradable(taker) {
+            if remaining == 0 {
+                break;
+            }
+            let filled = std::cmp::min(remaining, size);
+            remaining -= filled;
+            let notional = maker.price.checked_notional(filled).unwrap_or(u128::MAX);
+            cost = cost.saturating_add(notional);
+        }
+        cost
+    }
+
+    // match an incoming order against the opposite side, best price first. within a level
+    // the matching policy decides who fills, and every fill trades at the maker's price.
+    // an uncross passes its `auction_price`, which every fill trades at instead
+    fn resolve(&mut self, taker: &mut Order, auction_price: Option<Price>) {
+        // the dynamic band is anchored before the order arrives, so a sweep can't walk it
+        let last_price = self.last_price();
+        while taker.quantity > 0 {
+            // a buy takes from the lowest ask, a sell from the highest bid
+            let best = match taker.side {
+                Side::Bid => self.best_ask(),
+                Side::Ask => self.best_bid(),
+            };
+            let within_auction = |price: Price| match (auction_price, taker.side) {
+                (None, _) => true,
+                (Some(auction), Side::Bid) => price <= auction,
+                (Some(auction), Side::Ask) => price >= auction,
+            };
+            let best = best.filter(|price| taker.crosses(*price) && within_auction(*price));
+            let Some(price) = best else {
+                break; // opposite side is empty or too far away
+            };
+            if auction_price.is_none() && !self.within_dynamic_band(last_price, price) {
+                self.interrupt();
+                break;
+            }
+
+            let orders = &self.book(taker.side.opposite())[&price];
+            let ids: Vec<u128> = orders.iter().map(|o| o.id).collect();
+            let resting: Vec<u128> = orders.iter().map(|o| o.quantity).collect();
+            let lot_size = self.rules.lot_size;
+            let mut allocations = self.matching.allocate(taker.quantity, &resting, lot_size);
+            if !keeps_contract(&allocations, taker.quantity, &resting) {
+                // a custom policy got it wrong, FIFO still lets the order trade
+                allocations = Fifo.allocate(taker.quantity, &resting, lot_size);
+            }
+
+            // refilled icebergs and anything left over get another round at this price
+            let before = (taker.quantity, self.sequence);
+            for (id, allocation) in ids.into_iter().zip(allocations) {
+                if taker.quantity == 0 {
+                    break;
+                }
+                if allocation > 0 && !self.match_maker(taker, price, id, allocation, auction_price) {
+                    return;
+                }
+            }
+            if (taker.quantity, self.sequence) == before {
+                break; // nothing traded or changed, another round wouldn't either
+            }
+        }
+    }
+
+    // trade up to `allocation` of `taker` against the resting order `id` at `level`, or at
+    // `auction_price` in an uncross. false if the taker can't trade any further
+    fn match_maker(
+        &mut self,
+        taker: &mut Order,
+        level: Price,
+        id: u128,
+        allocation: u128,
+        auction_price: Option<Price>,
+    ) -> bool {
+        // trades print when they happen, a triggered stop or an amended order may be
+        // much older than that
+        let (price, time) = (auction_price.unwrap_or(level), self.now());
+        let loc = self.index[&id];
+        let book = match loc.side {
+            Side::Bid => &mut self.buy_orders,
+            Side::Ask => &mut self.sell_orders,
+        };
+        let orders = book.get_mut(&level).expect("index points at a missing level");
+        let slot = Self::slot(orders, loc.priority).expect("index points at a missing order");
+        let maker = &mut orders[slot];
+
+        let mode = self.self_trade_prevention;
+        if maker.account == taker.account && mode != SelfTradePrevention::Allow {
+            let maker_total = maker.quantity + maker.hidden;
+            let (taker_cancelled, maker_cancelled) = match mode {
+                SelfTradePrevention::CancelNewest => (taker.quantity, 0),
+                SelfTradePrevention::CancelOldest => (0, maker_total),
+                SelfTradePrevention::CancelBoth => (taker.quantity, maker_total),
+                _ => {
+                    let decrement = std::cmp::min(taker.quantity, maker_total);
+                    (decrement, decrement)
+                }
+            };
+            if let Some(self_trades) = &mut self.self_trades {
+                self_trades.push(SelfTradePrevented {
+                    mode,
+                    taker_order_id: taker.id,
+                    maker_order_id: maker.id,
+                    taker_cancelled,
+                    maker_cancelled,
+                });
+            }
+
+            let taker_freed = lock_for(taker, taker_cancelled);
+            taker.quantity -= taker_cancelled;
+            taker.locked -= taker_freed;
+            let maker_freed = lock_for(maker, maker_cancelled);
+            if maker_cancelled == maker_total {
+                let gone = orders.remove(slot);
+                if orders.is_empty() {
+                    book.remove(&level);
+                }
+                self.index.remove(&gone.id);
+                self.release(&gone);
+                self.emit(BookEvent::OrderCancelled {
+                    order_id: id,
+                    account: gone.account,
+                });
+            } else if maker_cancelled > 0 {
+                // the reserve shrinks before the displayed quantity does
+                let from_reserve = std::cmp::min(maker.hidden, maker_cancelled);
+                maker.hidden -= from_reserve;
+                maker.quantity -= maker_cancelled - from_reserve;
+                maker.locked -= maker_freed;
+                let remaining = maker.quantity;
+                if let Some(ledger) = &mut self.ledger {
+                    ledger.release(maker.account, asset_locked_by(maker.side), maker_freed);
+                }
+                let account = maker.account;
+                self.emit(BookEvent::OrderReduced {
+                    order_id: id,
+                    account,
+                    remaining,
+                });
+            }
+            if let Some(ledger) = &mut self.ledger {
+                ledger.release(taker.account, asset_locked_by(taker.side), taker_freed);
+            }
+            // an uncrossing taker is still on the book as far as the feed knows
+            if auction_price.is_some() && taker_cancelled > 0 {
+                let (order_id, account) = (taker.id, taker.account);
+                self.emit(match displayed(taker) {
+                    0 => BookEvent::OrderCancelled { order_id, account },
+                    remaining => BookEvent::OrderReduced {
+                        order_id,
+                        account,
+                        remaining,
+                    },
+                });
+            }
+            return true;
+        }
+
+        let mut match_quantity = allocation.min(taker.quantity).min(maker.quantity);
+        if maker.reduce_only {
+            let reducible = reducible(&self.positions, maker.account, maker.side);
+            if reducible == 0 {
+                // the position it was reducing is gone, so it can't trade any more
+                let gone = orders.remove(slot);
+                if orders.is_empty() {
+                    book.remove(&level);
+                }
+                self.index.remove(&gone.id);
+                self.release(&gone);
+                self.emit(BookEvent::OrderCancelled {
+                    order_id: id,
+                    account: gone.account,
+                });
+                return true;
+            }
+            match_quantity = std::cmp::min(match_quantity, reducible);
+        }
+        let market_buy = taker.side == Side::Bid && taker.order_type == OrderType::Market;
+        if market_buy && self.ledger.is_some() {
+            // the book may have changed since the sweep was priced, never spend more
+            let affordable = taker.locked / std::cmp::max(price.units(), 1) as u128;
+            if affordable == 0 {
+                return false;
+            }
+            match_quantity = std::cmp::min(match_quantity, affordable);
+        }
+        if let Some(ledger) = &mut self.ledger {
+            let cost = price.checked_notional(match_quantity).expect("covered by a lock");
+            // a limit buy locked at its own price, which may be worse than the maker's
+            let taker_consumed = match (taker.side, taker.order_type) {
+                (Side::Bid, OrderType::Limit) => {
+                    taker.price.checked_notional(match_quantity).expect("covered by a lock")
+                }
+                (Side::Bid, _) => cost,
+                (Side::Ask, _) => match_quantity,
+            };
+            // in an uncross a resting buy can also trade below its own price
+            let maker_consumed = match maker.side {
+                Side::Bid => level.checked_notional(match_quantity).expect("covered by a lock"),
+                Side::Ask => match_quantity,
+            };
+            let (buyer, seller, consumed) = match taker.side {
+                Side::Bid => (taker.account, maker.account, taker_consumed),
+                Side::Ask => (maker.account, taker.account, maker_consumed),
+            };
+            ledger.settle(buyer, seller, match_quantity, cost, consumed);
+            taker.locked -= taker_consumed;
+            maker.locked -= maker_consumed;
+        }
+        maker.quantity -= match_quantity;
+        let maker_account = maker.account;
+        let maker_side = maker.side;
+        taker.quantity -= match_quantity;
+        let mut maker_remaining = maker.quantity;
+        let mut refilled = None;
+
+        // Remove filled orders and empty price levels
+        if maker_remaining == 0 {
+            let mut filled = orders.remove(slot);
+            if filled.hidden > 0 {
+                // refill the peak from the reserve, at the back of the queue
+                let peak = filled.peak.expect("only icebergs have a hidden reserve");
+                filled.quantity = std::cmp::min(peak, filled.hidden);
+                filled.hidden -= filled.quantity;
+                filled.priority = self.next_priority;
+                self.next_priority += 1;
+                if let Some(loc) = self.index.get_mut(&id) {
+                    loc.priority = filled.priority;
+                }
+                maker_remaining = filled.quantity; // only the displayed part is reported
+                refilled = Some(filled.quantity);
+                orders.push(filled);
+            } else {
+                self.index.remove(&id);
+                if orders.is_empty() {
+                    book.remove(&level);
+                }
+            }
+        }
+
+        let signed = match taker.side {
+            Side::Bid => match_quantity as i128,
+            Side::Ask => -(match_quantity as i128),
+        };
+        *self.positions.entry(taker.account).or_default() += signed;
+        *self.positions.entry(maker_account).or_default() -= signed;
+
+        self.emit(BookEvent::OrderExecuted {
+            order_id: id,
+            account: maker_account,
+            quantity: match_quantity,
+            remaining: if refilled.is_some() { 0 } else { maker_remaining },
+        });
+        if let Some(quantity) = refilled {
+            self.emit(BookEvent::OrderAdded {
+                order_id: id,
+                account: maker_account,
+                side: maker_side,
+                price: level,
+                quantity,
+            });
+        }
+        if auction_price.is_some() {
+            self.emit(BookEvent::OrderExecuted {
+                order_id: taker.id,
+                account: taker.account,
+                quantity: match_quantity,
+                remaining: displayed(taker),
+            });
+        }
+
+        let trade_id = self.total_trades;
+        self.total_trades += 1;
+        let trade = Transaction {
+            trade_id,
+            price,
+            quantity: match_quantity,
+            time,
+            aggressor_side: taker.side,
+            taker_order_id: taker.id,
+            maker_order_id: id,
+            taker_remaining: taker.quantity,
+            maker_remaining,
+        };
+        self.transactions.push(trade.clone());
+        self.emit(BookEvent::TradePrinted {
+            trade,
+            taker_account: taker.account,
+            maker_account,
+        });
+        self.trigger_stops(price);
+        true
+    }
+
+    // the number of the last event, a snapshot taken now continues with the next one
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // every event after `sequence` that is still kept, oldest first. if the first one
+    // doesn't follow `sequence` the ones in between are gone, start from a new snapshot
+    pub fn events_since(&self, sequence: u64) -> &[SequencedEvent] {
+        let start = self.events.partition_point(|e| e.sequence <= sequence);
+        &self.events[start..]
+    }
+
+    fn emit(&mut self, event: BookEvent) {
+        self.sequence += 1;
+        self.events.push(SequencedEvent {
+            sequence: self.sequence,
+            time: self.now(),
+            event,
+        });
+        if self.events.len() >= 2 * EVENTS_KEPT {
+            self.events.drain(..EVENTS_KEPT);
+        }
+    }
+
+    // drop the event history, the sequence carries on where it was
+    pub(crate) fn forget_events(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn display(&self) {
+        println!("Order Book Stats");
+        println!("-------------------");
+        println!("bids");
+        for (_, order) in self.buy_orders.iter() {
+            for ord in order {
+                println!("Bid: price: {}, quantity: {}", ord.price, ord.quantity);
+            }
+        }
+        println!("-------------------");
+        println!("asks");
+        for (_, order) in self.sell_orders.iter() {
+            for ord in order {
+                println!("Ask: price: {}, quantity: {}", ord.price, ord.quantity);
+            }
+        }
+        println!("-------------------");
+        println!("transactions");
+        for transaction in self.transactions.iter() {
+            println!(
+                "Transaction: price: {}, quantity: {}, time: {:?}",
+                transaction.price, transaction.quantity, transaction.time
+            );
+        }
+    }
+
+    pub fn display_depth_chart(&self) {
+        let depth = self.depth(10);
+        let widest = depth.bids.iter().chain(&depth.asks).map(|l| l.quantity).max();
+        let bar = |level: &Level| {
+            // 40 characters for the largest level
+            let width = level.quantity * 40 / widest.unwrap_or(1).max(1);
+            "#".repeat(width.max(1) as usize)
+        };
+        println!("Depth");
+        println!("-------------------");
+        for level in depth.asks.iter().rev() {
+            println!("{:>12} {:>8} {}", level.price, level.quantity, bar(level));
+        }
+        println!("-------------------");
+        for level in &depth.bids {
+            println!("{:>12} {:>8} {}", level.price, level.quantity, bar(level));
+        }
+    }
+
+    // the best `levels` price levels on each side
+    pub fn depth(&self, levels: usize) -> Depth {
+        let level = |(price, orders): (&Price, &Vec<Order>)| aggregate(*price, orders);
+        Depth {
+            bids: self.buy_orders.iter().rev().take(levels).map(level).collect(),
+            asks: self.sell_orders.iter().take(levels).map(level).collect(),
+        }
+    }
+
+    // one price level, empty if nothing rests there
+    pub fn level(&self, side: Side, price: Price) -> Level {
+        let orders = self.book(side).get(&price).map_or(&[][..], |orders| orders.as_slice());
+        aggregate(price, orders)
+    }
+
+    // every order `account` has resting on the book, oldest first per level
+    pub fn open_orders(&self, account: AccountId) -> Vec<Order> {
+        self.buy_orders
+            .values()
+            .rev()
+            .chain(self.sell_orders.values())
+            .flatten()
+            .filter(|o| o.account == account)
+            .cloned()
+            .collect()
+    }
+
+    // every order on the book, resting or waiting for its trigger
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        let resting = self.buy_orders.values().chain(self.sell_orders.values());
+        let stops = self.stop_buys.values().chain(self.stop_sells.values());
+        resting.chain(stops).flatten().chain(&self.pending_stops)
+    }
+
+    pub fn get_buy_order(&self, id: u128) -> Result<&Order, OrderBookError> {
+        self.get_order(id, Side::Bid)
+    }
+
+    pub fn get_mut_buy_order(&mut self, id: u128) -> Result<&mut Order, OrderBookError> {
+        self.get_mut_order(id, Side::Bid)
+    }
+
+    pub fn get_sell_order(&self, id: u128) -> Result<&Order, OrderBookError> {
+        self.get_order(id, Side::Ask)
+    }
+
+    pub fn get_mut_sell_order(&mut self, id: u128) -> Result<&mut Order, OrderBookError> {
+        self.get_mut_order(id, Side::Ask)
+    }
+
+    pub fn get_buy_order_quantity(&self, id: u128) -> Result<u128, OrderBookError> {
+        self.get_buy_order(id).map(|ord| ord.quantity)
+    }
+
+    pub fn get_sell_order_quantity(&self, id: u128) -> Result<u128, OrderBookError> {
+        self.get_sell_order(id).map(|ord| ord.quantity)
+    }
+
+    fn get_order(&self, id: u128, side: Side) -> Result<&Order, OrderBookError> {
+        let loc = self.locate(id, side)?;
+        let orders = &self.book(side)[&loc.price];
+        let slot = Self::slot(orders, loc.priority).expect("index points at a missing order");
+        Ok(&orders[slot])
+    }
+
+    fn get_mut_order(&mut self, id: u128, side: Side) -> Result<&mut Order, OrderBookError> {
+        let loc = self.locate(id, side)?;
+        let orders = self
+            .book_mut(side)
+            .get_mut(&loc.price)
+            .expect("index points at a missing level");
+        let slot = Self::slot(orders, loc.priority).expect("index points at a missing order");
+        Ok(&mut orders[slot])
+    }
+
+    // WrongSide if the order rests on the other side of the book
+    fn locate(&self, id: u128, side: Side) -> Result<OrderLocation, OrderBookError> {
+        match self.index.get(&id) {
+            Some(loc) if loc.side == side => Ok(*loc),
+            Some(loc) => Err(OrderBookError::WrongSide { id, side: loc.side }),
+            None => Err(OrderBookError::UnknownOrderId(id)),
+        }
+    }
+
+    // orders in a level are always sorted by priority
+    fn slot(orders: &[Order], priority: u64) -> Option<usize> {
+        orders.binary_search_by_key(&priority, |o| o.priority).ok()
+    }
+
+    fn book(&self, side: Side) -> &BTreeMap<Price, Vec<Order>> {
+        match side {
+            Side::Bid => &self.buy_orders,
+            Side::Ask => &self.sell_orders,
+        }
+    }
+
+    fn book_mut(&mut self, side: Side) -> &mut BTreeMap<Price, Vec<Order>> {
+        match side {
+            Side::Bid => &mut self.buy_orders,
+            Side::Ask => &mut self.sell_orders,
+        }
+    }
+
+    pub fn get_tot_orders(&self) -> &u128 {
+        &self.total_orders
+    }
+}
+
+// how much of `order`'s lock covers `quantity` of it. market buys aren't locked per unit,
+// whatever they hold is released once they finish matching
+fn lock_for(order: &Order, quantity: u128) -> u128 {
+    if order.locked == 0 {
+        return 0; // the book has no ledger
+    }
+    match (order.side, order.order_type) {
+        (Side::Ask, _) => quantity,
+        (Side::Bid, OrderType::Market) => 0,
+        (Side::Bid, _) => order.price.checked_notional(quantity).expect("covered by a lock"),
+    }
+}
+
+fn aggregate(price: Price, orders: &[Order]) -> Level {
+    Level {
+        price,
+        quantity: orders.iter().map(|o| o.quantity).sum(),
+        orders: orders.len(),
+    }
+}
+
+// what the book would show of `order` if it rested now
+fn displayed(order: &Order) -> u128 {
+    order.peak.map_or(order.quantity, |peak| std::cmp::min(peak, order.quantity))
+}
+
+// buys pay in quote, sells deliver base
+fn asset_locked_by(side: Side) -> Asset {
+    match side {
+        Side::Bid => Asset::Quote,
+        Side::Ask => Asset::Base,
+    }
+}
+
+// what MatchingPolicy::allocate promises: one allocation per resting order, none more
+// than its order, adding up to as much as the level can fill
+fn keeps_contract(allocations: &[u128], quantity: u128, resting: &[u128]) -> bool {
+    let fits = allocations.iter().zip(resting).all(|(allocation, size)| allocation <= size);
+    let total = allocations.iter().try_fold(0u128, |total, a| total.checked_add(*a));
+    allocations.len() == resting.len()
+        && fits
+        && total == Some(std::cmp::min(quantity, resting.iter().sum()))
+}
+
+// how much `side` can trade for `account` without growing its position
+fn reducible(positions: &HashMap<AccountId, i128>, account: AccountId, side: Side) -> u128 {
+    let position = positions.get(&account).copied().unwrap_or(0);
+    match side {
+        Side::Bid if position < 0 => position.unsigned_abs(),
+        Side::Ask if position > 0 => position.unsigned_abs(),
+        _ => 0,
+    }
+}
+
+impl<P: MatchingPolicy + Clone> OrderBook<P> {
+    // copy of the book that is safe to publish: iceberg reserves, balances, positions and
+    // stops waiting for their trigger are stripped, and only the latest trades are kept
+    pub fn public_view(&self) -> Self {
+        let mut view = self.clone();
+        view.transactions.drain(..self.transactions.len().saturating_sub(PUBLIC_TRADES));
+        view.expiries.clear(); // would name the stops too
+        for orders in view.buy_orders.values_mut().chain(view.sell_orders.values_mut()) {
+            for ord in orders {
+                *ord = ord.redacted();
+            }
+        }
+        view.ledger = None;
+        view.positions.clear();
+        view.stop_buys.clear();
+        view.stop_sells.clear();
+        view.stop_index.clear();
+        view.pending_stops.clear();
+        view.self_trades = None;
+        view.forget_events(); // served on their own by `events_since`
+        view
+    }
+}
+
+impl FillReport {
+    fn from_fills(
+        order_id: u128,
+        quantity: u128,
+        fills: Vec<Transaction>,
+        self_trades: Vec<SelfTradePrevented>,
+    ) -> Self {
+        let filled_quantity: u128 = fills.iter().map(|t| t.quantity).sum();
+        let notional = fills.iter().try_fold(0u128, |total, t| {
+            total.checked_add(t.price.checked_notional(t.quantity)?)
+        });
+        FillReport {
+            order_id,
+            filled_quantity,
+            average_price: notional
+                .filter(|_| filled_quantity > 0)
+                .map(|notional| Price::notional_to_f64(notional) / filled_quantity as f64),
+            unfilled_quantity: quantity - filled_quantity,
+            fills,
+            self_trades,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_order_book() {
+        let a = OrderBook::build();
+        let b = OrderBook {
+            total_orders: 0,
+            buy_orders: BTreeMap::new(),
+            sell_orders: BTreeMap::new(),
+            transactions: Vec::new(),
+            total_trades: 0,
+            index: HashMap::new(),
+            next_priority: 0,
+            clock: Clock::System,
+            expiries: BTreeSet::new(),
+            post_only_mode: PostOnlyMode::Reject,
+            self_trade_prevention: SelfTradePrevention::Allow,
+            matching: Matching::Fifo,
+            auction: false,
+            indicative: None,
+            session: SessionState::Continuous,
+            schedule: Schedule::default(),
+            bands: PriceBands::default(),
+            reference_price: None,
+            resume_at: None,
+            events: Vec::new(),
+            sequence: 0,
+            self_trades: None,
+            positions: HashMap::new(),
+            stop_buys: BTreeMap::new(),
+            stop_sells: BTreeMap::new(),
+            stop_index: HashMap::new(),
+            pending_stops: VecDeque::new(),
+            rules: TradingRules::default(),
+            ledger: None,
+        };
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_buy() {
+        let mut a = OrderBook::build();
+        a.buy(2, 1).unwrap();
+
+        assert_eq!(a.buy_orders.len(), 1);
+        assert_eq!(a.total_orders, 1);
+    }
+
+    #[test]
+    fn test_sell() {
+        let mut a = OrderBook::build();
+        a.sell(2, 1).unwrap();
+
+        assert_eq!(a.sell_orders.len(), 1);
+        assert_eq!(a.total_orders, 1);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut a = OrderBook::build();
+        a.buy(2, 1).unwrap();
+
+        assert!(a.cancel(0).is_ok());
+        a.display();
+        println!("{}", a.buy_orders.len());
+
+        assert_eq!(a.buy_orders.len(), 0);
+    }
+
+    #[test]
     fn test_order_matching_buy_aggressive() {
         let mut a = OrderBook::build();
-        // Place a sell order first
-        a.sell(false, 100, 10);
-        // Place a buy order that should match
-        a.buy(true, 100, 5);
+        // Place a sell order first
+        a.sell(100, 10).unwrap();
+        // Place a buy order that should match
+        a.buy(100, 5).unwrap();
+
+        // Should have 1 transaction
+        assert_eq!(a.transactions.len(), 1);
+        // Sell order should still exist with remaining quantity
+        assert_eq!(a.sell_orders.len(), 1);
+        // Buy order should be fully filled and removed
+        assert_eq!(a.buy_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_order_matching_sell_aggressive() {
+        let mut a = OrderBook::build();
+        // Place a buy order first
+        a.buy(100, 10).unwrap();
+        // Place a sell order that should match
+        a.sell(100, 5).unwrap();
+
+        // Should have 1 transaction
+        assert_eq!(a.transactions.len(), 1);
+        // Buy order should still exist with remaining quantity
+        assert_eq!(a.buy_orders.len(), 1);
+        // Sell order should be fully filled and removed
+        assert_eq!(a.sell_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_exact_order_match() {
+        let mut a = OrderBook::build();
+        // Place a sell order
+        a.sell(100, 10).unwrap();
+        // Place a buy order with exact same quantity
+        a.buy(100, 10).unwrap();
+
+        // Should have 1 transaction
+        assert_eq!(a.transactions.len(), 1);
+        // Both orders should be fully filled and removed
+        assert_eq!(a.buy_orders.len(), 0);
+        assert_eq!(a.sell_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_no_matching_orders() {
+        let mut a = OrderBook::build();
+        // Place a sell order at higher price
+        a.sell(100, 10).unwrap();
+        // Place a buy order at lower price (no match)
+        a.buy(90, 5).unwrap();
+
+        // Should have no transactions
+        assert_eq!(a.transactions.len(), 0);
+        // Both orders should remain
+        assert_eq!(a.buy_orders.len(), 1);
+        assert_eq!(a.sell_orders.len(), 1);
+    }
+
+    #[test]
+    fn test_incoming_order_walks_levels() {
+        let mut a = OrderBook::build();
+        a.sell(100, 10).unwrap();
+        a.sell(95, 5).unwrap();
+        a.buy(100, 8).unwrap();
+        a.buy(98, 7).unwrap();
+
+        // the first buy takes all of 95 then 3 at 100, the second buy can't reach 100
+        assert_eq!(a.transactions.len(), 2);
+        assert_eq!(a.transactions[0].price, Price::from(95));
+        assert_eq!(a.transactions[0].quantity, 5);
+        assert_eq!(a.transactions[1].price, Price::from(100));
+        assert_eq!(a.transactions[1].quantity, 3);
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 7);
+        assert_eq!(a.get_buy_order_quantity(3).unwrap(), 7);
+        assert_eq!(a.buy_orders.len(), 1);
+        assert_eq!(a.sell_orders.len(), 1);
+    }
+
+    #[test]
+    fn test_zero_quantity_rejection() {
+        let mut a = OrderBook::build();
+        // Try to place orders with zero quantity
+        assert!(a.buy(100, 0).is_err());
+        assert!(a.sell(100, 0).is_err());
+
+        // Should have no orders
+        assert_eq!(a.buy_orders.len(), 0);
+        assert_eq!(a.sell_orders.len(), 0);
+        assert_eq!(a.total_orders, 0);
+    }
+
+    #[test]
+    fn test_order_priority() {
+        let mut a = OrderBook::build();
+        // Place multiple sell orders at same price
+        a.sell(100, 5).unwrap();
+        a.sell(100, 3).unwrap();
+        a.sell(100, 7).unwrap();
+
+        // Place a buy order that should match the first one
+        a.buy(100, 4).unwrap();
+
+        // Should have 1 transaction
+        assert_eq!(a.transactions.len(), 1);
+        // First sell order should be partially filled
+        assert_eq!(a.sell_orders.len(), 1);
+        // Buy order should be fully filled and removed
+        assert_eq!(a.buy_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_transaction_details() {
+        let mut a = OrderBook::build();
+        a.sell(100, 10).unwrap();
+        a.buy(100, 5).unwrap();
+
+        // Check transaction details
+        assert_eq!(a.transactions.len(), 1);
+        let transaction = &a.transactions[0];
+        assert_eq!(transaction.price, Price::from(100));
+        assert_eq!(transaction.quantity, 5);
+        assert!(transaction.time > SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_market_buy_sweeps_asks() {
+        let mut a = OrderBook::build();
+        a.sell(101, 5).unwrap();
+        a.sell(100, 5).unwrap();
+        a.sell(102, 5).unwrap();
+
+        let fill = a.market_buy(8).unwrap();
+
+        // best ask first, then the next level out
+        assert_eq!(fill.filled_quantity, 8);
+        assert_eq!(fill.unfilled_quantity, 0);
+        assert_eq!(fill.average_price, Some((100.0 * 5.0 + 101.0 * 3.0) / 8.0));
+        assert_eq!(a.transactions.len(), 2);
+        assert_eq!(a.transactions[0].price, Price::from(100));
+        assert_eq!(a.transactions[1].price, Price::from(101));
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 2);
+        assert_eq!(a.sell_orders.len(), 2);
+        // market orders never rest
+        assert_eq!(a.buy_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_market_sell_partial_fill() {
+        let mut a = OrderBook::build();
+        a.buy(99, 4).unwrap();
+        a.buy(100, 3).unwrap();
+
+        let fill = a.market_sell(10).unwrap();
+
+        assert_eq!(fill.filled_quantity, 7);
+        assert_eq!(fill.unfilled_quantity, 3);
+        assert_eq!(a.transactions[0].price, Price::from(100));
+        assert_eq!(a.transactions[1].price, Price::from(99));
+        assert_eq!(a.buy_orders.len(), 0);
+        assert_eq!(a.sell_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_market_order_empty_book() {
+        let mut a = OrderBook::build();
+
+        let fill = a.market_buy(5).unwrap();
+
+        assert_eq!(fill.filled_quantity, 0);
+        assert_eq!(fill.average_price, None);
+        assert_eq!(fill.unfilled_quantity, 5);
+        assert!(a.market_sell(0).is_err());
+    }
+
+    #[test]
+    fn test_submit_limit_reports_fills() {
+        let mut a = OrderBook::build();
+        a.submit(NewOrder::limit(Side::Ask, 100, 4)).unwrap();
+
+        let fill = a.submit(NewOrder::limit(Side::Bid, 101, 10)).unwrap();
+
+        assert_eq!(fill.order_id, 1);
+        assert_eq!(fill.filled_quantity, 4);
+        assert_eq!(fill.unfilled_quantity, 6);
+        // the remainder rests as a limit bid
+        let resting = a.get_buy_order(1).unwrap();
+        assert_eq!(resting.side(), Side::Bid);
+        assert_eq!(resting.order_type(), OrderType::Limit);
+        assert_eq!(resting.quantity, 6);
+    }
 
-        // Should have 1 transaction
-        assert_eq!(a.transactions.len(), 1);
-        // Sell order should still exist with remaining quantity
-        assert_eq!(a.sell_orders.len(), 1);
-        // Buy order should be fully filled and removed
-        assert_eq!(a.buy_orders.len(), 0);
+    #[test]
+    fn test_submit_rejects_zero_quantity() {
+        let mut a = OrderBook::build();
+
+        assert!(a.submit(NewOrder::limit(Side::Bid, 100, 0)).is_err());
+        assert!(a.submit(NewOrder::market(Side::Ask, 0)).is_err());
+        assert_eq!(a.total_orders, 0);
     }
 
     #[test]
-    fn test_order_matching_sell_aggressive() {
+    fn test_lookup_errors() {
         let mut a = OrderBook::build();
-        // Place a buy order first
-        a.buy(true, 100, 10);
-        // Place a sell order that should match
-        a.sell(false, 100, 5);
+        a.buy(100, 5).unwrap();
+
+        assert_eq!(
+            a.get_sell_order(0),
+            Err(OrderBookError::WrongSide { id: 0, side: Side::Bid })
+        );
+        assert_eq!(a.get_buy_order(7), Err(OrderBookError::UnknownOrderId(7)));
+        assert_eq!(a.cancel(7), Err(OrderBookError::UnknownOrderId(7)));
+        assert_eq!(a.buy(100, 0), Err(OrderBookError::ZeroQuantity));
+    }
 
-        // Should have 1 transaction
-        assert_eq!(a.transactions.len(), 1);
-        // Buy order should still exist with remaining quantity
-        assert_eq!(a.buy_orders.len(), 1);
-        // Sell order should be fully filled and removed
+    #[test]
+    fn test_cancel_middle_of_level() {
+        let mut a = OrderBook::build();
+        a.sell(100, 5).unwrap();
+        a.sell(100, 3).unwrap();
+        a.sell(100, 7).unwrap();
+
+        let cancelled = a.cancel(1).unwrap();
+
+        // exactly order 1 is gone, the others keep their place in the queue
+        assert_eq!(cancelled.id, 1);
+        assert_eq!(cancelled.quantity, 3);
+        let ids: Vec<u128> = a.sell_orders[&Price::from(100)].iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+        assert_eq!(a.get_sell_order(1), Err(OrderBookError::UnknownOrderId(1)));
+        assert_eq!(a.get_sell_order_quantity(2).unwrap(), 7);
+        assert_eq!(a.cancel(1), Err(OrderBookError::UnknownOrderId(1)));
+    }
+
+    #[test]
+    fn test_index_tracks_fills() {
+        let mut a = OrderBook::build();
+        a.sell(100, 5).unwrap();
+        a.sell(101, 5).unwrap();
+        a.buy(101, 7).unwrap();
+
+        // order 0 was filled away, order 1 is partially filled
+        assert_eq!(a.get_sell_order(0), Err(OrderBookError::UnknownOrderId(0)));
+        assert_eq!(a.get_sell_order_quantity(1).unwrap(), 3);
+        assert_eq!(a.index.len(), 1);
+        assert!(a.cancel(1).is_ok());
+        assert!(a.index.is_empty());
+    }
+
+    #[test]
+    fn test_execution_reports() {
+        let mut a = OrderBook::build();
+        let maker = a.sell(100, 5).unwrap();
+        assert!(maker.fills.is_empty());
+
+        let taker = a.buy(100, 8).unwrap();
+
+        assert_eq!(taker.fills.len(), 1);
+        let fill = &taker.fills[0];
+        assert_eq!(fill.trade_id, 0);
+        assert_eq!(fill.aggressor_side, Side::Bid);
+        assert_eq!(fill.taker_order_id, taker.order_id);
+        assert_eq!(fill.maker_order_id, maker.order_id);
+        assert_eq!(fill.taker_remaining, 3);
+        assert_eq!(fill.maker_remaining, 0);
+        assert_eq!(a.transactions, taker.fills);
+
+        // the resting bid is now the maker for an incoming sell
+        let taker = a.sell(100, 1).unwrap();
+        let fill = &taker.fills[0];
+        assert_eq!(fill.trade_id, 1);
+        assert_eq!(fill.aggressor_side, Side::Ask);
+        assert_eq!(fill.maker_order_id, 1);
+        assert_eq!(fill.maker_remaining, 2);
+        assert_eq!(fill.taker_remaining, 0);
+    }
+
+    #[test]
+    fn test_aggressive_buy_trades_at_ask() {
+        let mut a = OrderBook::build();
+        a.sell(100, 5).unwrap();
+
+        let report = a.buy(105, 5).unwrap();
+
+        // the buyer gets price improvement down to the resting ask
+        assert_eq!(report.fills[0].price, Price::from(100));
+        assert_eq!(report.average_price, Some(100.0));
+    }
+
+    #[test]
+    fn test_aggressive_sell_trades_at_bid() {
+        let mut a = OrderBook::build();
+        a.buy(100, 5).unwrap();
+        a.buy(98, 5).unwrap();
+
+        let report = a.sell(95, 7).unwrap();
+
+        // the seller gets price improvement up to each resting bid
+        assert_eq!(report.fills.len(), 2);
+        assert_eq!(report.fills[0].price, Price::from(100));
+        assert_eq!(report.fills[1].price, Price::from(98));
+        assert_eq!(report.fills[1].quantity, 2);
+        // nothing left over so nothing rests at 95
         assert_eq!(a.sell_orders.len(), 0);
     }
 
     #[test]
-    fn test_exact_order_match() {
+    fn test_ioc_cancels_remainder() {
         let mut a = OrderBook::build();
-        // Place a sell order
-        a.sell(false, 100, 10);
-        // Place a buy order with exact same quantity
-        a.buy(true, 100, 10);
+        a.sell(100, 3).unwrap();
 
-        // Should have 1 transaction
-        assert_eq!(a.transactions.len(), 1);
-        // Both orders should be fully filled and removed
+        let report = a
+            .submit(NewOrder::limit(Side::Bid, 100, 5).with_time_in_force(TimeInForce::Ioc))
+            .unwrap();
+
+        assert_eq!(report.filled_quantity, 3);
+        assert_eq!(report.unfilled_quantity, 2);
         assert_eq!(a.buy_orders.len(), 0);
+        assert!(a.index.is_empty());
+    }
+
+    #[test]
+    fn test_fok_rejects_without_side_effects() {
+        let mut a = OrderBook::build();
+        a.sell(100, 3).unwrap();
+        a.sell(101, 3).unwrap();
+        let before = a.clone();
+
+        // only 6 available at or below 101
+        let err = a
+            .submit(NewOrder::limit(Side::Bid, 101, 7).with_time_in_force(TimeInForce::Fok))
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::InsufficientLiquidity);
+        assert_eq!(a, before);
+
+        let report = a
+            .submit(NewOrder::limit(Side::Bid, 101, 6).with_time_in_force(TimeInForce::Fok))
+            .unwrap();
+        assert_eq!(report.filled_quantity, 6);
         assert_eq!(a.sell_orders.len(), 0);
     }
 
     #[test]
-    fn test_no_matching_orders() {
+    fn test_gtd_and_day_expiry() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(10 * 86_400 + 3_600);
         let mut a = OrderBook::build();
-        // Place a sell order at higher price
-        a.sell(false, 100, 10);
-        // Place a buy order at lower price (no match)
-        a.buy(true, 90, 5);
+        a.set_clock(Clock::Manual(start));
+
+        let gtd = TimeInForce::Gtd(start + Duration::from_secs(60));
+        a.submit(NewOrder::limit(Side::Bid, 99, 5).with_time_in_force(gtd)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 98, 5).with_time_in_force(TimeInForce::Day))
+            .unwrap();
+        a.buy(97, 5).unwrap();
+
+        a.advance_clock(Duration::from_secs(60));
+        let expired = a.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, 0);
+
+        // DAY lasts until midnight, GTC forever
+        a.advance_clock(Duration::from_secs(86_400));
+        let expired = a.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, 1);
+        assert_eq!(a.get_buy_order_quantity(2).unwrap(), 5);
+
+        let past = TimeInForce::Gtd(start);
+        assert_eq!(
+            a.submit(NewOrder::limit(Side::Bid, 99, 5).with_time_in_force(past)),
+            Err(OrderBookError::ExpiryInPast)
+        );
+    }
 
-        // Should have no transactions
-        assert_eq!(a.transactions.len(), 0);
-        // Both orders should remain
-        assert_eq!(a.buy_orders.len(), 1);
-        assert_eq!(a.sell_orders.len(), 1);
+    #[test]
+    fn test_expired_orders_never_match() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut a = OrderBook::build();
+        a.set_clock(Clock::Manual(start));
+        let gtd = TimeInForce::Gtd(start + Duration::from_secs(1));
+        a.submit(NewOrder::limit(Side::Ask, 100, 5).with_time_in_force(gtd)).unwrap();
+
+        // no sweep ran, but submit expires the ask before matching
+        a.advance_clock(Duration::from_secs(1));
+        let report = a.buy(100, 5).unwrap();
+
+        assert!(report.fills.is_empty());
+        assert_eq!(a.sell_orders.len(), 0);
+    }
+
+    #[test]
+    fn test_post_only_reject_and_reprice() {
+        let mut a = OrderBook::build_with_rules(TradingRules::new(1, 1)); // reprice by whole units
+        a.sell(100, 5).unwrap();
+
+        let crossing = NewOrder::limit(Side::Bid, 100, 5).post_only();
+        assert_eq!(a.submit(crossing.clone()), Err(OrderBookError::PostOnlyWouldCross));
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 5);
+
+        // passive post-only orders rest as normal
+        a.submit(NewOrder::limit(Side::Bid, 98, 5).post_only()).unwrap();
+
+        a.set_post_only_mode(PostOnlyMode::Reprice);
+        let report = a.submit(crossing).unwrap();
+        assert!(report.fills.is_empty());
+        assert_eq!(a.get_buy_order(report.order_id).unwrap().price(), Price::from(99));
+        assert_eq!(a.best_bid(), Some(Price::from(99)));
+        assert_eq!(a.best_ask(), Some(Price::from(100)));
     }
 
     #[test]
-    fn test_resolve_function() {
+    fn test_reduce_only_never_increases_position() {
         let mut a = OrderBook::build();
-        // Place multiple orders that can match
-        a.sell(false, 100, 10);
-        a.sell(false, 95, 5);
-        a.buy(true, 100, 8);
-        a.buy(true, 98, 7);
+        a.submit(NewOrder::limit(Side::Ask, 100, 10).for_account(2)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 100, 4).for_account(1)).unwrap();
+        assert_eq!(a.position(1), 4);
+        assert_eq!(a.position(2), -4);
+
+        // account 1 is long, so a reduce-only buy is rejected
+        let buy = NewOrder::limit(Side::Bid, 100, 1).for_account(1).reduce_only();
+        assert_eq!(a.submit(buy), Err(OrderBookError::ReduceOnlyWouldIncrease));
+
+        // a reduce-only sell is clipped to the 4 it holds
+        let sell = NewOrder::limit(Side::Ask, 105, 10).for_account(1).reduce_only();
+        let report = a.submit(sell).unwrap();
+        assert_eq!(a.get_sell_order_quantity(report.order_id).unwrap(), 4);
+
+        // once the position is closed elsewhere the resting reduce-only order can't trade
+        a.submit(NewOrder::limit(Side::Ask, 99, 4).for_account(1)).unwrap();
+        a.submit(NewOrder::market(Side::Bid, 20).for_account(3)).unwrap();
+        assert_eq!(a.position(1), 0);
+        assert!(a.get_sell_order(report.order_id).is_err());
+    }
 
-        // Manually resolve all possible matches
-        a.resolve();
+    #[test]
+    fn test_fok_counts_reduce_only_makers_by_position() {
+        let mut a = OrderBook::build();
+        a.submit(NewOrder::limit(Side::Ask, 100, 2).for_account(2)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 100, 2).for_account(1)).unwrap();
+        let sell = NewOrder::limit(Side::Ask, 101, 2).for_account(1).reduce_only();
+        a.submit(sell).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 90, 1).for_account(3)).unwrap();
+        a.submit(NewOrder::market(Side::Ask, 1).for_account(1)).unwrap();
+        assert_eq!(a.position(1), 1);
+
+        // 2 rest at 101, but only 1 of them can still trade
+        let fok = |quantity| {
+            NewOrder::limit(Side::Bid, 101, quantity)
+                .for_account(4)
+                .with_time_in_force(TimeInForce::Fok)
+        };
+        assert_eq!(a.submit(fok(2)), Err(OrderBookError::InsufficientLiquidity));
+        assert_eq!(a.submit(fok(1)).unwrap().filled_quantity, 1);
+        assert_eq!(a.position(1), 0);
+    }
 
-        // Should have multiple transactions
-        assert!(a.transactions.len() > 0);
-        // Orders should be properly matched and quantities updated
-        assert!(a.buy_orders.len() <= 2);
-        assert!(a.sell_orders.len() <= 2);
+    #[test]
+    fn test_amend_clips_reduce_only() {
+        let mut a = OrderBook::build();
+        a.submit(NewOrder::limit(Side::Ask, 100, 4).for_account(2)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 100, 4).for_account(1)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 99, 30).for_account(3)).unwrap();
+        let sell = NewOrder::limit(Side::Ask, 105, 4).for_account(1).reduce_only();
+        let id = a.submit(sell).unwrap().order_id;
+        let sell = NewOrder::limit(Side::Ask, 106, 4).for_account(1).reduce_only();
+        let other = a.submit(sell).unwrap().order_id;
+
+        // the same price and quantity changes nothing, not even the event feed
+        let sequence = a.sequence();
+        a.amend(id, 105, 4).unwrap();
+        assert_eq!(a.sequence(), sequence);
+
+        // growing past the position and crossing only sells what account 1 holds
+        let amended = a.amend(id, 99, 20).unwrap();
+        assert_eq!(amended.quantity, 0);
+        assert_eq!(a.position(1), 0);
+        assert_eq!(a.get_buy_order_quantity(2).unwrap(), 26);
+
+        // with nothing left to reduce the amend is rejected and the original stays
+        assert_eq!(a.amend(other, 107, 4), Err(OrderBookError::ReduceOnlyWouldIncrease));
+        assert_eq!(a.get_sell_order(other).unwrap().price, Price::from(106));
     }
 
     #[test]
-    fn test_zero_quantity_rejection() {
+    fn test_stop_market_triggers_on_last_trade() {
         let mut a = OrderBook::build();
-        // Try to place orders with zero quantity
-        a.buy(true, 100, 0);
-        a.sell(false, 100, 0);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        a.set_clock(Clock::Manual(start));
+        a.buy(95, 5).unwrap();
+        a.buy(90, 5).unwrap();
+        let stop = a.submit(NewOrder::stop_market(Side::Ask, 95, 4)).unwrap();
+        assert!(stop.fills.is_empty());
+        assert!(a.stop_index.contains_key(&stop.order_id));
+
+        // a trade at 95 triggers the sell stop, which sells into what is left of the bids
+        a.advance_clock(Duration::from_secs(3600));
+        a.sell(95, 2).unwrap();
+
+        assert!(a.stop_sells.is_empty());
+        assert_eq!(a.transactions.len(), 3);
+        assert_eq!(a.transactions[1].taker_order_id, stop.order_id);
+        assert_eq!(a.transactions[1].price, Price::from(95));
+        assert_eq!(a.transactions[1].quantity, 3);
+        assert_eq!(a.transactions[2].price, Price::from(90));
+        assert_eq!(a.get_buy_order_quantity(1).unwrap(), 4);
+        // the stop's trades print when they happen, not when it was submitted
+        assert!(a.transactions.iter().all(|t| t.time == start + Duration::from_secs(3600)));
+    }
 
-        // Should have no orders
-        assert_eq!(a.buy_orders.len(), 0);
-        assert_eq!(a.sell_orders.len(), 0);
-        assert_eq!(a.total_orders, 0);
+    #[test]
+    fn test_stop_limit_rests_after_trigger() {
+        let mut a = OrderBook::build();
+        a.sell(100, 1).unwrap();
+        a.sell(103, 5).unwrap();
+        let stop = a.submit(NewOrder::stop_limit(Side::Bid, 100, 101, 5)).unwrap();
+
+        a.buy(100, 1).unwrap();
+
+        // triggered at 100 but nothing offered at or below 101, so it rests
+        assert_eq!(a.get_buy_order_quantity(stop.order_id).unwrap(), 5);
+        assert_eq!(a.get_buy_order(stop.order_id).unwrap().order_type(), OrderType::Limit);
+        assert_eq!(
+            a.submit(NewOrder { trigger_price: None, ..NewOrder::stop_market(Side::Bid, 1, 1) }),
+            Err(OrderBookError::MissingTriggerPrice)
+        );
     }
 
     #[test]
-    fn test_order_priority() {
+    fn test_stop_cascade() {
         let mut a = OrderBook::build();
-        // Place multiple sell orders at same price
-        a.sell(false, 100, 5);
-        a.sell(false, 100, 3);
-        a.sell(false, 100, 7);
+        a.buy(100, 1).unwrap();
+        a.buy(99, 1).unwrap();
+        a.buy(98, 1).unwrap();
+        a.buy(97, 1).unwrap();
+        // the first stop's trade at 99 triggers the second, whose trade at 98 triggers nothing else
+        let first = a.submit(NewOrder::stop_market(Side::Ask, 100, 1)).unwrap();
+        let second = a.submit(NewOrder::stop_market(Side::Ask, 99, 1)).unwrap();
+        let untouched = a.submit(NewOrder::stop_market(Side::Ask, 90, 1)).unwrap();
+
+        a.sell(100, 1).unwrap();
+
+        let takers: Vec<(u128, Price)> =
+            a.transactions.iter().map(|t| (t.taker_order_id, t.price)).collect();
+        assert_eq!(takers, vec![
+                (7, Price::from(100)),
+                (first.order_id, Price::from(99)),
+                (second.order_id, Price::from(98)),
+            ]);
+        assert_eq!(a.best_bid(), Some(Price::from(97)));
+        assert!(a.cancel(untouched.order_id).is_ok());
+        assert!(a.stop_sells.is_empty());
+    }
 
-        // Place a buy order that should match the first one
-        a.buy(true, 100, 4);
+    #[test]
+    fn test_iceberg_refills_at_back_of_queue() {
+        let mut a = OrderBook::build();
+        let iceberg = a.submit(NewOrder::limit(Side::Ask, 100, 10).iceberg(3)).unwrap();
+        a.sell(100, 2).unwrap();
+
+        // only the peak is displayed
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 3);
+        assert_eq!(a.get_sell_order(0).unwrap().hidden_quantity(), 7);
+
+        // consuming the peak refills it behind order 1
+        let report = a.buy(100, 4).unwrap();
+        assert_eq!(report.fills[0].maker_order_id, iceberg.order_id);
+        assert_eq!(report.fills[0].maker_remaining, 3);
+        assert_eq!(report.fills[1].maker_order_id, 1);
+        let ids: Vec<u128> = a.sell_orders[&Price::from(100)].iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![1, 0]);
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 3);
+        assert_eq!(a.get_sell_order(0).unwrap().hidden_quantity(), 4);
+
+        // the reserve is still liquidity, the last refill is smaller than the peak
+        let report = a.market_buy(20).unwrap();
+        assert_eq!(report.filled_quantity, 1 + 7);
+        assert!(a.sell_orders.is_empty());
+        assert!(a.index.is_empty());
+    }
 
-        // Should have 1 transaction
-        assert_eq!(a.transactions.len(), 1);
-        // First sell order should be partially filled
-        assert_eq!(a.sell_orders.len(), 1);
-        // Buy order should be fully filled and removed
-        assert_eq!(a.buy_orders.len(), 0);
+    #[test]
+    fn test_public_view_hides_reserve() {
+        let mut a = OrderBook::build();
+        a.submit(NewOrder::limit(Side::Bid, 100, 50).iceberg(5)).unwrap();
+        let stop = NewOrder::stop_market(Side::Ask, 90, 3).with_time_in_force(TimeInForce::Day);
+        a.submit(stop.for_account(4)).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 100, 1).for_account(4)).unwrap();
+
+        let view = a.public_view();
+        assert!(view.positions.is_empty() && view.stop_index.is_empty());
+        assert!(view.expiries.is_empty() && !a.expiries.is_empty());
+        assert!(view.orders().all(|ord| ord.order_type == OrderType::Limit));
+        assert_eq!(a.position(4), -1);
+
+        let ord = view.get_buy_order(0).unwrap();
+        assert_eq!(ord.quantity, 4);
+        assert_eq!(ord.hidden_quantity(), 0);
+        assert_eq!(a.get_buy_order(0).unwrap().hidden_quantity(), 45);
+        assert_eq!(a.open_orders(0)[0].redacted(), *ord);
     }
 
     #[test]
-    fn test_transaction_details() {
+    fn test_amend_decrease_keeps_priority() {
         let mut a = OrderBook::build();
-        a.sell(false, 100, 10);
-        a.buy(true, 100, 5);
+        a.sell(100, 5).unwrap();
+        a.sell(100, 5).unwrap();
+
+        let amended = a.amend(0, 100, 2).unwrap();
+
+        assert_eq!(amended.quantity, 2);
+        let ids: Vec<u128> = a.sell_orders[&Price::from(100)].iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![0, 1]);
+        assert_eq!(a.buy(100, 1).unwrap().fills[0].maker_order_id, 0);
+    }
+
+    #[test]
+    fn test_amend_increase_or_reprice_loses_priority() {
+        let mut a = OrderBook::build();
+        a.sell(100, 5).unwrap();
+        a.sell(100, 5).unwrap();
+        a.sell(101, 5).unwrap();
+
+        a.amend(0, 100, 6).unwrap();
+        let ids: Vec<u128> = a.sell_orders[&Price::from(100)].iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![1, 0]);
+
+        // moving to 100 puts order 2 behind both orders already there
+        let amended = a.amend(2, 100, 5).unwrap();
+        assert_eq!(amended.price(), Price::from(100));
+        let ids: Vec<u128> = a.sell_orders[&Price::from(100)].iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![1, 0, 2]);
+        assert!(!a.sell_orders.contains_key(&Price::from(101)));
+        assert_eq!(a.amend(9, 100, 1), Err(OrderBookError::UnknownOrderId(9)));
+        assert_eq!(a.amend(1, 100, 0), Err(OrderBookError::ZeroQuantity));
+    }
+
+    #[test]
+    fn test_amend_across_spread_trades() {
+        let mut a = OrderBook::build();
+        a.sell(100, 3).unwrap();
+        a.buy(98, 5).unwrap();
+
+        let amended = a.amend(1, 100, 5).unwrap();
 
-        // Check transaction details
         assert_eq!(a.transactions.len(), 1);
-        let transaction = &a.transactions[0];
-        assert_eq!(transaction.price, 100);
-        assert_eq!(transaction.quantity, 5);
-        assert!(transaction.time > SystemTime::UNIX_EPOCH);
+        assert_eq!(a.transactions[0].taker_order_id, 1);
+        assert_eq!(amended.quantity, 2);
+        assert_eq!(a.best_bid(), Some(Price::from(100)));
+    }
+
+    // whole units of quote in ledger units
+    fn quote(whole: u64) -> u128 {
+        Price::from(whole).units() as u128
+    }
+
+    fn funded_book() -> OrderBook {
+        let mut ledger = Ledger::new();
+        ledger.deposit(1, Asset::Quote, quote(1000));
+        ledger.deposit(2, Asset::Base, 10);
+        let mut a = OrderBook::build();
+        a.set_ledger(ledger);
+        a
+    }
+
+    #[test]
+    fn test_ledger_locks_settles_and_releases() {
+        let mut a = funded_book();
+        a.submit(NewOrder::limit(Side::Ask, 100, 10).for_account(2)).unwrap();
+        assert_eq!(a.ledger().unwrap().account(2).base, Balance { available: 0, locked: 10 });
+
+        // locked at 101, filled at 100, the difference comes straight back
+        a.submit(NewOrder::limit(Side::Bid, 101, 4).for_account(1)).unwrap();
+        let ledger = a.ledger().unwrap();
+        assert_eq!(ledger.account(1).quote, Balance { available: quote(600), locked: 0 });
+        assert_eq!(ledger.account(1).base.available, 4);
+        assert_eq!(ledger.account(2).quote.available, quote(400));
+        assert_eq!(ledger.account(2).base, Balance { available: 0, locked: 6 });
+
+        // rejected without taking an id
+        assert_eq!(
+            a.submit(NewOrder::limit(Side::Bid, 99, 7).for_account(1)),
+            Err(OrderBookError::InsufficientBalance)
+        );
+        assert_eq!(
+            a.submit(NewOrder::limit(Side::Ask, 100, 1).for_account(3)),
+            Err(OrderBookError::InsufficientBalance)
+        );
+        assert_eq!(a.total_orders, 2);
+
+        a.cancel(0).unwrap();
+        assert_eq!(a.ledger().unwrap().account(2).base, Balance { available: 6, locked: 0 });
+    }
+
+    #[test]
+    fn test_ledger_pays_for_stops_up_front() {
+        let mut a = funded_book();
+        let stop = NewOrder::stop_limit(Side::Bid, 100, 100, 11).for_account(1);
+        assert_eq!(a.submit(stop), Err(OrderBookError::InsufficientBalance));
+        let stop = a.submit(NewOrder::stop_market(Side::Bid, 100, 5).for_account(1)).unwrap();
+        assert_eq!(a.ledger().unwrap().account(1).quote.locked, quote(500));
+        a.cancel(stop.order_id).unwrap();
+        assert_eq!(a.ledger().unwrap().account(1).quote.locked, 0);
+
+        // held at the trigger, but sweeping the asks once triggered costs more
+        a.submit(NewOrder::limit(Side::Ask, 100, 1).for_account(2)).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 120, 8).for_account(2)).unwrap();
+        let stop = a.submit(NewOrder::stop_market(Side::Bid, 100, 9).for_account(1)).unwrap();
+        let sequence = a.sequence();
+        a.submit(NewOrder::limit(Side::Bid, 100, 1).for_account(1)).unwrap();
+
+        let rejected = BookEvent::OrderRejected {
+            order_id: stop.order_id,
+            account: 1,
+            reason: OrderBookError::InsufficientBalance,
+        };
+        assert!(a.events_since(sequence).iter().any(|e| e.event == rejected));
+        let balance = Balance { available: quote(900), locked: 0 };
+        assert_eq!(a.ledger().unwrap().account(1).quote, balance);
+        assert_eq!(a.cancel(stop.order_id), Err(OrderBookError::UnknownOrderId(stop.order_id)));
+    }
+
+    #[test]
+    fn test_ledger_market_buys_and_amends() {
+        let mut a = funded_book();
+        a.submit(NewOrder::limit(Side::Ask, 100, 5).for_account(2)).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 300, 5).for_account(2)).unwrap();
+
+        // sweeping 7 costs 5 * 100 + 2 * 300
+        assert_eq!(
+            a.submit(NewOrder::market(Side::Bid, 7).for_account(1)),
+            Err(OrderBookError::InsufficientBalance)
+        );
+        let report = a.submit(NewOrder::market(Side::Bid, 6).for_account(1)).unwrap();
+        assert_eq!(report.filled_quantity, 6);
+        let balance = a.ledger().unwrap().account(1).quote;
+        assert_eq!(balance, Balance { available: quote(200), locked: 0 });
+
+        // moving a buy re-locks at the new price, shrinking it frees the difference
+        let id = a.submit(NewOrder::limit(Side::Bid, 20, 5).for_account(1)).unwrap().order_id;
+        assert_eq!(a.amend(id, 40, 6), Err(OrderBookError::InsufficientBalance));
+        a.amend(id, 40, 5).unwrap();
+        let balance = a.ledger().unwrap().account(1).quote;
+        assert_eq!(balance, Balance { available: 0, locked: quote(200) });
+        a.amend(id, 40, 2).unwrap();
+        let balance = a.ledger().unwrap().account(1).quote;
+        assert_eq!(balance, Balance { available: quote(120), locked: quote(80) });
+    }
+
+    fn self_trade_book(mode: SelfTradePrevention) -> OrderBook {
+        let mut a = OrderBook::build();
+        a.set_self_trade_prevention(mode);
+        a.submit(NewOrder::limit(Side::Ask, 100, 5).for_account(1)).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 101, 5).for_account(2)).unwrap();
+        a
+    }
+
+    #[test]
+    fn test_self_trade_cancel_modes() {
+        let buy = NewOrder::limit(Side::Bid, 101, 8).for_account(1);
+
+        // its own ask is first, so cancel newest rejects the whole order
+        let mut a = self_trade_book(SelfTradePrevention::CancelNewest);
+        assert_eq!(a.submit(buy.clone()), Err(OrderBookError::SelfTrade));
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 5);
+        assert_eq!(a.best_bid(), None);
+
+        // after some fills only the remainder is cancelled
+        let report = a.submit(NewOrder::limit(Side::Bid, 101, 8).for_account(2)).unwrap();
+        assert_eq!(report.filled_quantity, 5);
+        assert_eq!(report.self_trades[0].taker_cancelled, 3);
+        assert_eq!(report.self_trades[0].maker_cancelled, 0);
+        assert_eq!(a.get_sell_order(0), Err(OrderBookError::UnknownOrderId(0)));
+        assert_eq!(a.get_sell_order_quantity(1).unwrap(), 5);
+        assert_eq!(a.best_bid(), None);
+
+        let mut a = self_trade_book(SelfTradePrevention::CancelOldest);
+        let report = a.submit(buy.clone()).unwrap();
+        assert_eq!(report.self_trades[0].maker_order_id, 0);
+        assert_eq!(report.self_trades[0].maker_cancelled, 5);
+        assert_eq!(report.fills.len(), 1);
+        assert_eq!(report.fills[0].maker_order_id, 1);
+        assert_eq!(a.get_buy_order_quantity(2).unwrap(), 3);
+        assert_eq!(a.get_sell_order(0), Err(OrderBookError::UnknownOrderId(0)));
+
+        let mut a = self_trade_book(SelfTradePrevention::CancelBoth);
+        let report = a.submit(buy).unwrap();
+        assert!(report.fills.is_empty());
+        assert_eq!(report.unfilled_quantity, 8);
+        assert_eq!(a.best_bid(), None);
+        assert_eq!(a.best_ask(), Some(Price::from(101)));
+
+        // orders of different accounts still trade
+        let mut a = self_trade_book(SelfTradePrevention::CancelBoth);
+        let report = a.submit(NewOrder::limit(Side::Bid, 100, 5).for_account(3)).unwrap();
+        assert_eq!(report.filled_quantity, 5);
+    }
+
+    #[test]
+    fn test_self_trade_decrement_and_cancel() {
+        let mut a = OrderBook::build();
+        a.set_self_trade_prevention(SelfTradePrevention::DecrementAndCancel);
+        a.submit(NewOrder::limit(Side::Ask, 100, 10).iceberg(4).for_account(1)).unwrap();
+
+        // the reserve goes first, then the displayed peak
+        let report = a.submit(NewOrder::limit(Side::Bid, 100, 7).for_account(1)).unwrap();
+        assert_eq!(report.self_trades[0].maker_cancelled, 7);
+        assert_eq!(a.get_sell_order(0).unwrap().hidden_quantity(), 0);
+        assert_eq!(a.get_sell_order_quantity(0).unwrap(), 3);
+
+        let report = a.submit(NewOrder::limit(Side::Bid, 100, 5).for_account(1)).unwrap();
+        assert_eq!(report.self_trades[0].taker_cancelled, 3);
+        assert_eq!(a.best_ask(), None);
+        assert_eq!(a.get_buy_order_quantity(2).unwrap(), 2);
+        assert!(a.transactions.is_empty());
+    }
+
+    #[test]
+    fn test_self_trade_prevention_releases_locks_and_checks_fok() {
+        let mut a = funded_book();
+        a.ledger_mut().unwrap().deposit(1, Asset::Base, 5);
+        a.set_self_trade_prevention(SelfTradePrevention::CancelBoth);
+        a.submit(NewOrder::limit(Side::Ask, 100, 5).for_account(1)).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 100, 5).for_account(2)).unwrap();
+
+        // the account's own ask is first in the queue, so the taker would be cancelled there
+        let fok = NewOrder::limit(Side::Bid, 100, 5)
+            .for_account(1)
+            .with_time_in_force(TimeInForce::Fok);
+        assert_eq!(a.submit(fok.clone()), Err(OrderBookError::InsufficientLiquidity));
+        a.set_self_trade_prevention(SelfTradePrevention::CancelNewest);
+        assert_eq!(a.submit(fok.clone()), Err(OrderBookError::SelfTrade));
+
+        a.set_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        assert_eq!(a.submit(fok).unwrap().filled_quantity, 5);
+        let account = a.ledger().unwrap().account(1);
+        assert_eq!(account.base, Balance { available: 10, locked: 0 });
+        assert_eq!(account.quote, Balance { available: quote(500), locked: 0 });
+    }
+
+    #[test]
+    fn test_pro_rata_book() {
+        let mut a = OrderBook::build();
+        a.set_matching(Matching::ProRata);
+        a.sell(100, 2).unwrap();
+        a.sell(100, 6).unwrap();
+        a.sell(101, 4).unwrap();
+
+        let report = a.buy(100, 5).unwrap();
+        let fills: Vec<(u128, u128)> =
+            report.fills.iter().map(|t| (t.maker_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(0, 2), (1, 3)]);
+
+        // a level that runs out moves on to the next price
+        let report = a.buy(101, 5).unwrap();
+        let fills: Vec<(u128, u128)> =
+            report.fills.iter().map(|t| (t.maker_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(1, 3), (2, 2)]);
+        assert_eq!(a.get_sell_order_quantity(2).unwrap(), 2);
+    }
+
+    // newest first, nothing a preset does
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    struct Lifo;
+
+    impl MatchingPolicy for Lifo {
+        fn allocate(&self, quantity: u128, resting: &[u128], _lot_size: u128) -> Vec<u128> {
+            let mut remaining = quantity;
+            let mut allocations: Vec<u128> = resting
+                .iter()
+                .rev()
+                .map(|&size| {
+                    let fill = std::cmp::min(remaining, size);
+                    remaining -= fill;
+                    fill
+                })
+                .collect();
+            allocations.reverse();
+            allocations
+        }
+    }
+
+    #[test]
+    fn test_custom_matching_policy() {
+        let mut a = OrderBook::build_with_policy(Lifo);
+        a.sell(100, 2).unwrap();
+        a.sell(100, 6).unwrap();
+
+        let report = a.buy(100, 7).unwrap();
+        let fills: Vec<(u128, u128)> =
+            report.fills.iter().map(|t| (t.maker_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(0, 1), (1, 6)]); // printed in queue order all the same
+        assert_eq!(a.public_view().get_sell_order_quantity(0), Ok(1));
+    }
+
+    // breaks every part of the contract in turn
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    struct Broken(u8);
+
+    impl MatchingPolicy for Broken {
+        fn allocate(&self, quantity: u128, resting: &[u128], _lot_size: u128) -> Vec<u128> {
+            match self.0 {
+                0 => vec![0; resting.len()],
+                1 => vec![quantity],
+                _ => resting.iter().map(|size| size + 1).collect(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_broken_policy_falls_back_to_fifo() {
+        for broken in 0..3 {
+            let mut a = OrderBook::build_with_policy(Broken(broken));
+            a.sell(100, 2).unwrap();
+            a.sell(100, 6).unwrap();
+
+            let report = a.buy(100, 7).unwrap();
+            let fills: Vec<(u128, u128)> =
+                report.fills.iter().map(|t| (t.maker_order_id, t.quantity)).collect();
+            assert_eq!(fills, vec![(0, 2), (1, 5)]);
+        }
+    }
+
+    #[test]
+    fn test_auction_uncrosses_at_one_price() {
+        let mut a = OrderBook::build();
+        a.start_auction();
+        a.sell(100, 5).unwrap();
+        a.sell(101, 5).unwrap();
+        a.buy(102, 4).unwrap();
+        a.buy(101, 4).unwrap();
+        a.buy(100, 3).unwrap();
+        assert!(a.transactions.is_empty());
+        assert_eq!(a.market_buy(1).unwrap_err(), OrderBookError::NotAllowedInAuction);
+
+        // 100 trades 5, 101 trades 8 and 102 trades 4
+        let expected = Uncross { price: Price::from(101), volume: 8, imbalance: -2 };
+        assert_eq!(a.indicative(), Some(expected));
+
+        let trades = a.uncross();
+        assert!(trades.iter().all(|t| t.price == Price::from(101)));
+        let fills: Vec<(u128, u128, u128)> =
+            trades.iter().map(|t| (t.taker_order_id, t.maker_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(2, 0, 4), (3, 0, 1), (3, 1, 3)]);
+        assert!(!a.in_auction());
+        assert_eq!(a.indicative(), None);
+        assert_eq!(a.best_bid(), Some(Price::from(100)));
+        assert_eq!(a.get_sell_order_quantity(1).unwrap(), 2);
+
+        // back to continuous trading
+        assert_eq!(a.buy(101, 2).unwrap().fills.len(), 1);
+    }
+
+    #[test]
+    fn test_auction_tie_breakers() {
+        let indicative = |buy: u128, sell: u128| {
+            let mut a = OrderBook::build();
+            a.start_auction();
+            a.buy(101, buy).unwrap();
+            a.sell(100, sell).unwrap();
+            a.indicative().unwrap().price
+        };
+        // same volume and imbalance at both prices, the surplus side decides
+        assert_eq!(indicative(6, 5), Price::from(101));
+        assert_eq!(indicative(5, 6), Price::from(100));
+        // balanced with no reference price, the lower one
+        assert_eq!(indicative(5, 5), Price::from(100));
+
+        // balanced, nearest the last trade
+        let mut a = OrderBook::build();
+        a.sell(105, 1).unwrap();
+        a.buy(105, 1).unwrap();
+        a.start_auction();
+        a.buy(101, 5).unwrap();
+        a.sell(100, 5).unwrap();
+        assert_eq!(a.indicative().unwrap().price, Price::from(101));
+
+        // a reference price set for the auction wins over the last trade
+        a.set_reference_price(99);
+        a.cancel(3).unwrap();
+        a.sell(100, 5).unwrap();
+        assert_eq!(a.indicative().unwrap().price, Price::from(100));
+    }
+
+    #[test]
+    fn test_uncross_works_around_self_trades() {
+        let mut a = OrderBook::build();
+        a.set_self_trade_prevention(SelfTradePrevention::CancelNewest);
+        a.start_auction();
+        a.submit(NewOrder::limit(Side::Bid, 102, 3).for_account(1)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 98, 4)).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 96, 1).for_account(1)).unwrap();
+        assert_eq!(a.indicative().unwrap().price, Price::from(102));
+
+        // the bid at 102 only meets its own account's ask, so 98 takes over
+        let trades = a.uncross();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].taker_order_id, trades[0].price), (1, Price::from(98)));
+        assert_eq!(a.best_ask(), None);
+        assert_eq!(a.best_bid(), Some(Price::from(98)));
+        assert_eq!(a.reference_price(), Some(Price::from(98)));
+        assert!(a.get_buy_order(0).is_err());
+    }
+
+    #[test]
+    fn test_auction_settles_at_uncross_price() {
+        let mut a = funded_book();
+        a.start_auction();
+        a.submit(NewOrder::limit(Side::Ask, 100, 4).for_account(2)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 102, 6).for_account(1)).unwrap();
+        assert_eq!(a.ledger().unwrap().account(1).quote.locked, quote(612));
+
+        let trades = a.uncross();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, Price::from(102));
+        // 4 paid at 102, the 2 left stays locked at its limit
+        let ledger = a.ledger().unwrap();
+        assert_eq!(ledger.account(1).quote, Balance { available: quote(388), locked: quote(204) });
+        assert_eq!(ledger.account(2).quote.available, quote(408));
+        assert_eq!(a.get_buy_order_quantity(1).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_session_gates_actions() {
+        let mut a = OrderBook::build();
+        a.buy(99, 5).unwrap();
+        a.transition(SessionState::Halted).unwrap();
+        assert_eq!(a.sell(101, 5).unwrap_err(), OrderBookError::BookHalted);
+        assert_eq!(a.amend(0, 98, 5).unwrap_err(), OrderBookError::BookHalted);
+        assert!(a.cancel(0).is_ok());
+
+        a.transition(SessionState::Closed).unwrap();
+        let closed = OrderBookError::NotAllowedInSession(SessionState::Closed);
+        assert_eq!(a.buy(100, 1).unwrap_err(), closed);
+        assert_eq!(
+            a.transition(SessionState::Continuous).unwrap_err(),
+            OrderBookError::InvalidTransition {
+                from: SessionState::Closed,
+                to: SessionState::Continuous
+            }
+        );
+
+        // orders collect before the open and uncross into continuous trading
+        a.transition(SessionState::PreOpen).unwrap();
+        a.buy(101, 5).unwrap();
+        a.sell(100, 3).unwrap();
+        a.transition(SessionState::OpeningAuction).unwrap();
+        let frozen = OrderBookError::NotAllowedInSession(SessionState::OpeningAuction);
+        assert_eq!(a.cancel(1).unwrap_err(), frozen);
+        let trades = a.transition(SessionState::Continuous).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, Price::from(101));
+        assert!(!a.in_auction());
+    }
+
+    #[test]
+    fn test_schedule_drives_session() {
+        let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+        let midnight = SystemTime::UNIX_EPOCH + Duration::from_secs(20_000 * 24 * 60 * 60);
+        let mut a = OrderBook::build();
+        a.set_clock(Clock::Manual(midnight + hours(8)));
+        a.transition(SessionState::Closed).unwrap();
+        a.set_schedule(Schedule::daily(vec![
+            (hours(8), SessionState::PreOpen),
+            (hours(9), SessionState::Continuous),
+            (hours(16), SessionState::ClosingAuction),
+            (hours(17), SessionState::Closed),
+        ]));
+
+        a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::PreOpen);
+        a.advance_clock(hours(1));
+        a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::Continuous);
+
+        // a halt outlasts the schedule until the close
+        a.transition(SessionState::Halted).unwrap();
+        a.advance_clock(hours(7));
+        a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::Halted);
+        a.advance_clock(hours(1));
+        a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::Closed);
+    }
+
+    #[test]
+    fn test_static_band_rejects_far_prices() {
+        let mut a = OrderBook::build();
+        a.set_price_bands(PriceBands::default().with_static(1000));
+        // no reference yet, anything goes
+        a.buy(50, 3).unwrap();
+        a.set_reference_price(100);
+        // out of band now, but it can still be shrunk, just not grown
+        assert_eq!(a.amend(0, 50, 2).unwrap().quantity, 2);
+        assert_eq!(a.amend(0, 50, 4).unwrap_err(), OrderBookError::PriceOutOfBand(Price::from(50)));
+
+        assert_eq!(a.buy(111, 1).unwrap_err(), OrderBookError::PriceOutOfBand(Price::from(111)));
+        a.sell(110, 1).unwrap();
+        assert_eq!(a.amend(1, 89, 1).unwrap_err(), OrderBookError::PriceOutOfBand(Price::from(89)));
+        // market orders have no price to check
+        assert_eq!(a.market_buy(1).unwrap().filled_quantity, 1);
+    }
+
+    #[test]
+    fn test_volatility_interruption() {
+        let mut a = OrderBook::build();
+        a.set_clock(Clock::Manual(SystemTime::UNIX_EPOCH));
+        a.set_price_bands(PriceBands::default().with_dynamic(500, Duration::from_secs(60)));
+        a.sell(100, 1).unwrap();
+        a.buy(100, 1).unwrap();
+        a.sell(104, 1).unwrap();
+        a.sell(110, 5).unwrap();
+
+        // 104 is within 5% of the last trade, 110 isn't
+        let report = a.buy(111, 3).unwrap();
+        assert_eq!(report.filled_quantity, 1);
+        assert_eq!(a.session(), SessionState::Halted);
+        assert_eq!(a.best_bid(), Some(Price::from(111)));
+        assert_eq!(a.sell(108, 1).unwrap_err(), OrderBookError::BookHalted);
+
+        // the schedule keeps the halt until its time is up, then reopens by auction
+        a.advance_clock(Duration::from_secs(30));
+        a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::Halted);
+        a.advance_clock(Duration::from_secs(30));
+        a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::OpeningAuction);
+        a.sell(108, 1).unwrap();
+        assert_eq!(a.indicative().unwrap().price, Price::from(110));
+
+        a.advance_clock(Duration::from_secs(60));
+        let trades = a.run_schedule().unwrap();
+        assert_eq!(a.session(), SessionState::Continuous);
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<u128>(), 2);
+        assert!(trades.iter().all(|t| t.price == Price::from(110)));
+        assert_eq!(a.reference_price(), Some(Price::from(110)));
+    }
+
+    #[test]
+    fn test_interruption_stops_a_stop_cascade() {
+        let mut a = OrderBook::build();
+        a.set_price_bands(PriceBands::default().with_dynamic(500, Duration::from_secs(60)));
+        a.sell(100, 1).unwrap();
+        a.buy(100, 1).unwrap();
+        a.buy(99, 1).unwrap();
+        a.buy(80, 5).unwrap();
+        let first = a.submit(NewOrder::stop_market(Side::Ask, 99, 2)).unwrap().order_id;
+        let second = a.submit(NewOrder::stop_market(Side::Ask, 99, 2)).unwrap().order_id;
+        let sequence = a.sequence();
+
+        // the first stop would sell at 80, far outside the band, and halts the book
+        a.sell(99, 1).unwrap();
+        assert_eq!(a.session(), SessionState::Halted);
+        let rejected = BookEvent::OrderRejected {
+            order_id: first,
+            account: 0,
+            reason: OrderBookError::BookHalted,
+        };
+        assert!(a.events_since(sequence).iter().any(|e| e.event == rejected));
+
+        // the second is still waiting for the reopening, and can be cancelled meanwhile
+        assert!(a.orders().any(|ord| ord.id == second));
+        assert_eq!(a.cancel(second).unwrap().id, second);
+        assert!(a.pending_stops.is_empty());
+        assert_eq!(a.best_bid(), Some(Price::from(80)));
+    }
+
+    #[test]
+    fn test_depth_aggregates_levels() {
+        let mut a = OrderBook::build();
+        a.buy(99, 2).unwrap();
+        a.buy(100, 3).unwrap();
+        a.buy(100, 4).unwrap();
+        a.buy(98, 1).unwrap();
+        a.sell(101, 5).unwrap();
+        // only the displayed peak of an iceberg counts
+        a.submit(NewOrder::limit(Side::Ask, 102, 10).iceberg(2)).unwrap();
+
+        let depth = a.depth(2);
+        let level = |price: u64, quantity, orders| Level {
+            price: Price::from(price),
+            quantity,
+            orders,
+        };
+        assert_eq!(depth.bids, vec![level(100, 7, 2), level(99, 2, 1)]);
+        assert_eq!(depth.asks, vec![level(101, 5, 1), level(102, 2, 1)]);
+        assert_eq!(a.depth(0), Depth::default());
+    }
+
+    type Visible = (BTreeMap<Price, Vec<(u128, u128)>>, BTreeMap<Price, Vec<(u128, u128)>>);
+
+    // (id, displayed quantity) per level, in queue order
+    fn visible(a: &OrderBook) -> Visible {
+        let levels = |book: &BTreeMap<Price, Vec<Order>>| {
+            let level = |orders: &Vec<Order>| orders.iter().map(|o| (o.id, o.quantity)).collect();
+            book.iter().map(|(price, orders)| (*price, level(orders))).collect()
+        };
+        (levels(&a.buy_orders), levels(&a.sell_orders))
+    }
+
+    // what a feed consumer would build from a snapshot and the events after it
+    fn replay(snapshot: Visible, events: &[SequencedEvent]) -> Visible {
+        let (mut bids, mut asks) = snapshot;
+        let mut located: HashMap<u128, (Side, Price)> = HashMap::new();
+        for (side, book) in [(Side::Bid, &bids), (Side::Ask, &asks)] {
+            for (price, orders) in book {
+                for (id, _) in orders {
+                    located.insert(*id, (side, *price));
+                }
+            }
+        }
+        for event in events {
+            let (id, remaining) = match event.event {
+                BookEvent::OrderAdded { order_id, side, price, quantity, .. } => {
+                    let book = if side == Side::Bid { &mut bids } else { &mut asks };
+                    book.entry(price).or_default().push((order_id, quantity));
+                    located.insert(order_id, (side, price));
+                    continue;
+                }
+                BookEvent::OrderReduced { order_id, remaining, .. } => (order_id, remaining),
+                BookEvent::OrderExecuted { order_id, remaining, .. } => (order_id, remaining),
+                BookEvent::OrderCancelled { order_id, .. } => (order_id, 0),
+                BookEvent::TradePrinted { .. } | BookEvent::OrderRejected { .. } => continue,
+            };
+            let (side, price) = located[&id];
+            let book = if side == Side::Bid { &mut bids } else { &mut asks };
+            let level = book.get_mut(&price).unwrap();
+            let slot = level.iter().position(|(o, _)| *o == id).unwrap();
+            if remaining == 0 {
+                level.remove(slot);
+                located.remove(&id);
+            } else {
+                level[slot].1 = remaining;
+            }
+            if level.is_empty() {
+                book.remove(&price);
+            }
+        }
+        (bids, asks)
+    }
+
+    #[test]
+    fn test_events_rebuild_the_book() {
+        let mut a = OrderBook::build();
+        a.set_self_trade_prevention(SelfTradePrevention::DecrementAndCancel);
+        a.submit(NewOrder::limit(Side::Ask, 100, 10).iceberg(3)).unwrap();
+        a.sell(100, 2).unwrap();
+        a.sell(101, 5).unwrap();
+        a.buy(99, 4).unwrap();
+        a.buy(100, 4).unwrap(); // refills the iceberg behind order 1
+        a.amend(2, 101, 4).unwrap();
+        a.amend(1, 102, 2).unwrap();
+        a.cancel(3).unwrap();
+        a.submit(NewOrder::limit(Side::Ask, 105, 5).for_account(7)).unwrap();
+        a.submit(NewOrder::limit(Side::Bid, 105, 2).for_account(7)).unwrap();
+
+        let events = a.events_since(0);
+        assert!(events.iter().map(|e| e.sequence).eq(1..=a.sequence()));
+        assert!(matches!(events[0].event, BookEvent::OrderAdded { order_id: 0, quantity: 3, .. }));
+        let refill = BookEvent::OrderAdded {
+            order_id: 0,
+            account: 0,
+            side: Side::Ask,
+            price: Price::from(100),
+            quantity: 3,
+        };
+        assert!(events.iter().any(|e| e.event == refill));
+        let trades = events.iter().filter(|e| matches!(e.event, BookEvent::TradePrinted { .. }));
+        assert_eq!(trades.count(), a.transactions.len());
+
+        assert_eq!(replay(Visible::default(), events), visible(&a));
+    }
+
+    #[test]
+    fn test_events_continue_a_snapshot() {
+        let mut a = OrderBook::build();
+        a.buy(99, 3).unwrap();
+        a.sell(101, 3).unwrap();
+        let snapshot = visible(&a.public_view());
+        let sequence = a.sequence();
+
+        // an auction with an iceberg that uncrosses in part
+        a.start_auction();
+        a.submit(NewOrder::limit(Side::Bid, 102, 8).iceberg(2)).unwrap();
+        a.sell(100, 3).unwrap();
+        a.cancel(0).unwrap();
+        a.uncross();
+        a.sell(102, 1).unwrap();
+
+        assert_eq!(a.events_since(sequence)[0].sequence, sequence + 1);
+        assert_eq!(replay(snapshot, a.events_since(sequence)), visible(&a));
+        assert!(a.events_since(a.sequence()).is_empty());
+    }
+
+    #[test]
+    fn test_event_history_is_bounded() {
+        let mut a = OrderBook::build();
+        for _ in 0..2 * EVENTS_KEPT {
+            a.buy(99, 1).unwrap();
+        }
+        let events = a.events_since(0);
+        assert_eq!(events.len(), EVENTS_KEPT);
+        assert_eq!(events[0].sequence, EVENTS_KEPT as u64 + 1);
+        assert_eq!(a.sequence(), 2 * EVENTS_KEPT as u64);
+        assert!(a.public_view().events_since(0).is_empty());
+
+        // the public view keeps the latest trades only
+        for _ in 0..PUBLIC_TRADES + 10 {
+            a.sell(99, 1).unwrap();
+        }
+        let view = a.public_view();
+        assert_eq!(view.transactions().len(), PUBLIC_TRADES);
+        assert_eq!(view.transactions().last(), a.transactions().last());
+        assert_eq!(a.self_trades, None);
+    }
+
+    #[test]
+    fn test_journal_replay_rebuilds_the_book() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let expiry = TimeInForce::Gtd(start + Duration::from_secs(30));
+        let commands = [
+            Command::Submit(NewOrder::limit(Side::Ask, 100, 10).iceberg(3)),
+            Command::Submit(NewOrder::limit(Side::Ask, 101, 5).with_time_in_force(expiry)),
+            Command::Submit(NewOrder::limit(Side::Bid, 99, 4)),
+            Command::Submit(NewOrder::market(Side::Bid, 4)),
+            Command::Amend {
+                id: 2,
+                price: Price::from(98),
+                quantity: 6,
+            },
+            Command::Cancel { id: 7 }, // rejected, and rejected again on replay
+            Command::Submit(NewOrder::stop_market(Side::Bid, 101, 2)),
+            Command::Transition(SessionState::Halted),
+            Command::Submit(NewOrder::limit(Side::Bid, 102, 1)),
+            Command::Tick, // order 1 expires
+            Command::Transition(SessionState::Continuous),
+            Command::Submit(NewOrder::limit(Side::Bid, 100, 3)),
+        ];
+
+        let mut a = OrderBook::build();
+        let mut journal = Vec::new();
+        for (i, command) in commands.into_iter().enumerate() {
+            let time = start + Duration::from_secs(10 * i as u64);
+            let _ = a.apply(time, &command);
+            let entry = JournalEntry {
+                offset: i as u64 + 1,
+                time,
+                symbol: "ABC".to_string(),
+                command,
+            };
+            // through the journal's own format
+            journal.push(serde_json::to_string(&entry).unwrap());
+        }
+        assert!(a.get_sell_order(1).is_err());
+        assert_eq!(a.transactions.len(), 4);
+
+        let mut b = OrderBook::build();
+        for line in &journal {
+            let entry: JournalEntry = serde_json::from_str(line).unwrap();
+            let _ = b.apply(entry.time, &entry.command);
+        }
+        assert_eq!(a, b);
     }
 }
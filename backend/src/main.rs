@@ -1,5 +1,5 @@
 use backend::order_generator::OrderGenerator;
-use backend::OrderBook;
+use backend::{NewOrder, OrderBook, Price};
 
 fn main() {
     println!("This is a simple clob");
@@ -11,12 +11,8 @@ fn main() {
 
     if let Some(ord_gen) = OrderGenerator::build(0.5, 0.5) {
         for _ in 0..20 {
-            let (buy_sell, price) = ord_gen.gen_order(10.0);
-            if buy_sell{
-                ord_book.buy(true, price, 1);
-            } else {
-                ord_book.sell(false, price, 1);
-            }
+            let (side, price) = ord_gen.gen_order(10.0, Price::new(1, 2));
+            let _ = ord_book.submit(NewOrder::limit(side, price, 1));
             ord_book.display();
         }
     } else {
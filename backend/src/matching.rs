@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+// how an incoming order's quantity is shared among the resting orders at one price level
+pub trait MatchingPolicy {
+    // `resting` is the displayed quantity of each order at the level, in time priority.
+    // returns how much each of them fills, in the same order. it must return exactly one
+    // allocation per resting order, each at most that order's quantity, adding up to
+    // min(quantity, sum of resting). a book overrules an allocation that breaks this with
+    // Fifo, so a faulty policy can't stall matching
+    fn allocate(&self, quantity: u128, resting: &[u128], lot_size: u128) -> Vec<u128>;
+}
+
+// price-time priority: the oldest order fills completely before the next one starts
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+// every order fills in proportion to its size, rounded down to whole lots. the residual
+// lots go out one at a time in time priority, round robin, so the split is deterministic
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct ProRata;
+
+// `fifo_percent` of the incoming quantity is matched FIFO (so the top of the queue is
+// rewarded), the rest pro-rata over what is left at the level
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Hybrid {
+    pub fifo_percent: u8,
+}
+
+// the policy an OrderBook is configured with
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Matching {
+    #[default]
+    Fifo,
+    ProRata,
+    Hybrid { fifo_percent: u8 },
+}
+
+impl MatchingPolicy for Fifo {
+    fn allocate(&self, quantity: u128, resting: &[u128], _lot_size: u128) -> Vec<u128> {
+        let mut remaining = quantity;
+        resting
+            .iter()
+            .map(|&size| {
+                let fill = std::cmp::min(remaining, size);
+                remaining -= fill;
+                fill
+            })
+            .collect()
+    }
+}
+
+impl MatchingPolicy for ProRata {
+    fn allocate(&self, quantity: u128, resting: &[u128], lot_size: u128) -> Vec<u128> {
+        let lot_size = std::cmp::max(lot_size, 1);
+        let total: u128 = resting.iter().sum();
+        let quantity = std::cmp::min(quantity, total);
+        let mut allocations: Vec<u128> = resting
+            .iter()
+            .map(|&size| share(quantity, size, total) / lot_size * lot_size)
+            .collect();
+
+        let mut residual = quantity - allocations.iter().sum::<u128>();
+        while residual > 0 {
+            for (allocation, &size) in allocations.iter_mut().zip(resting) {
+                let step = std::cmp::min(std::cmp::min(lot_size, residual), size - *allocation);
+                *allocation += step;
+                residual -= step;
+                if residual == 0 {
+                    break;
+                }
+            }
+        }
+        allocations
+    }
+}
+
+impl MatchingPolicy for Hybrid {
+    fn allocate(&self, quantity: u128, resting: &[u128], lot_size: u128) -> Vec<u128> {
+        let lot_size = std::cmp::max(lot_size, 1);
+        let percent = std::cmp::min(self.fifo_percent, 100) as u128;
+        let fifo_quantity = share(quantity, percent, 100) / lot_size * lot_size;
+
+        let mut allocations = Fifo.allocate(fifo_quantity, resting, lot_size);
+        let left: Vec<u128> = resting.iter().zip(&allocations).map(|(size, a)| size - a).collect();
+        let pro_rata = ProRata.allocate(quantity - fifo_quantity, &left, lot_size);
+        for (allocation, extra) in allocations.iter_mut().zip(pro_rata) {
+            *allocation += extra;
+        }
+        allocations
+    }
+}
+
+impl MatchingPolicy for Matching {
+    fn allocate(&self, quantity: u128, resting: &[u128], lot_size: u128) -> Vec<u128> {
+        match *self {
+            Matching::Fifo => Fifo.allocate(quantity, resting, lot_size),
+            Matching::ProRata => ProRata.allocate(quantity, resting, lot_size),
+            Matching::Hybrid { fifo_percent } => {
+                Hybrid { fifo_percent }.allocate(quantity, resting, lot_size)
+            }
+        }
+    }
+}
+
+// quantity * part / total rounded down, without overflowing for huge quantities
+fn share(quantity: u128, part: u128, total: u128) -> u128 {
+    if total == 0 {
+        return 0;
+    }
+    match quantity.checked_mul(part) {
+        Some(product) => product / total,
+        None => quantity / total * part,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_and_pro_rata() {
+        assert_eq!(Fifo.allocate(12, &[5, 10, 5], 1), vec![5, 7, 0]);
+
+        // 5 * 2/8 = 1.25 and 5 * 6/8 = 3.75, the odd lot goes to the older order
+        assert_eq!(ProRata.allocate(5, &[2, 6], 1), vec![2, 3]);
+        // every share floors to 0, so the lots go out in time priority
+        assert_eq!(ProRata.allocate(2, &[4, 4, 4], 1), vec![1, 1, 0]);
+        // whole lots only, the residual lot round robins
+        assert_eq!(ProRata.allocate(30, &[10, 20, 30], 10), vec![10, 10, 10]);
+        assert_eq!(ProRata.allocate(100, &[10, 20], 10), vec![10, 20]);
+    }
+
+    #[test]
+    fn test_hybrid_rewards_top_of_queue() {
+        let hybrid = Hybrid { fifo_percent: 40 };
+        // 4 FIFO to the first order, then 6 pro-rata over [6, 10] is 2.25 and 3.75
+        let allocations = hybrid.allocate(10, &[10, 10], 1);
+        assert_eq!(allocations, vec![7, 3]);
+        assert_eq!(allocations.iter().sum::<u128>(), 10);
+
+        assert_eq!(Matching::Hybrid { fifo_percent: 100 }.allocate(12, &[5, 10], 1), vec![5, 7]);
+        assert_eq!(Matching::Hybrid { fifo_percent: 0 }.allocate(5, &[2, 6], 1), vec![2, 3]);
+    }
+}
@@ -1,12 +1,14 @@
 use rand::Rng;
 
+use crate::{Price, Side};
+
 #[derive(Clone, Copy)]
 pub struct Probability(f64);
 
 impl Probability {
     pub fn new(val: f64) -> Option<Self> {
         // refactor so all decimals are actually decimals
-        if val > 1.0 || val < 0.0 {
+        if !(0.0..=1.0).contains(&val) {
             println!("Probability value out of range: {}", val);
             None
         } else {
@@ -35,22 +37,24 @@ impl OrderGenerator {
         })
     }
 
-    pub fn gen_order(&self, mut center: f64) -> (bool, u64) {
+    // price is rounded to the nearest multiple of `tick`
+    pub fn gen_order(&self, mut center: f64, tick: Price) -> (Side, Price) {
         let mut rand_gen = rand::rng();
 
-        let buy_sell = rand_gen.random_bool(0.5);
+        let side = if rand_gen.random_bool(0.5) { Side::Bid } else { Side::Ask };
 
 
         let vol = self.vol.get();
         let rand_noise = rand_gen.random_range(0.0..vol);
-        if rand_gen.random_bool(self.rng.get() as f64) {// buy and sell are picked from the same distribution 
+        if rand_gen.random_bool(self.rng.get()) {// buy and sell are picked from the same distribution 
                                                         // --> distributions should be on edge of each other
-            center = (1.0 + rand_noise) * center;
+            center *= 1.0 + rand_noise;
         } else {
-            center = (1.0 - rand_noise) * center;
+            center *= 1.0 - rand_noise;
         }
 
-        (buy_sell, center as u64) // center gets rounded
+        let ticks = (center / tick.to_f64()).round().max(1.0) as u64;
+        (side, tick.checked_mul(ticks).unwrap_or(Price::MAX))
     }
 
     pub fn start() {}
@@ -0,0 +1,264 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::ParsePriceError;
+
+// fixed-point price, stored as a whole number of 10^-DECIMALS units so comparisons
+// and arithmetic are exact. `Price::from(100)` is 100.0, `Price::new(10125, 2)` is 101.25
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Default)]
+pub struct Price(u64);
+
+const SCALE: u64 = 10u64.pow(Price::DECIMALS);
+
+impl Price {
+    // the finest precision any price can have. a book sets its own scale, at most this,
+    // with TradingRules::with_decimals and a coarser tick size on top of that. prices are
+    // stored at this scale whatever the book's, so notionals and the ledger's quote
+    // balances add up across every book
+    pub const DECIMALS: u32 = 8;
+    pub const ZERO: Price = Price(0);
+    pub const MAX: Price = Price(u64::MAX);
+
+    // `mantissa` scaled down by `decimals` places. panics like integer overflow does if
+    // the result can't be represented
+    pub const fn new(mantissa: u64, decimals: u32) -> Self {
+        assert!(decimals <= Price::DECIMALS, "too many decimal places for a price");
+        match mantissa.checked_mul(10u64.pow(Price::DECIMALS - decimals)) {
+            Some(units) => Price(units),
+            None => panic!("price overflow"),
+        }
+    }
+
+    // the fewest decimal places that write the price exactly
+    pub fn decimal_places(self) -> u32 {
+        let mut places = Price::DECIMALS;
+        let mut frac = self.0 % SCALE;
+        while places > 0 && frac.is_multiple_of(10) {
+            frac /= 10;
+            places -= 1;
+        }
+        places
+    }
+
+    pub const fn from_units(units: u64) -> Self {
+        Price(units)
+    }
+
+    // number of 10^-DECIMALS units
+    pub const fn units(self) -> u64 {
+        self.0
+    }
+
+    // nearest representable price, None for negative, NaN or out of range values
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let units = (value * SCALE as f64).round();
+        if units.is_finite() && units >= 0.0 && units <= u64::MAX as f64 {
+            Some(Price(units as u64))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / SCALE as f64
+    }
+
+    pub fn checked_add(self, other: Price) -> Option<Price> {
+        self.0.checked_add(other.0).map(Price)
+    }
+
+    pub fn checked_sub(self, other: Price) -> Option<Price> {
+        self.0.checked_sub(other.0).map(Price)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Price> {
+        self.0.checked_mul(factor).map(Price)
+    }
+
+    // price * quantity in price units, which doesn't fit a Price for large orders
+    pub fn checked_notional(self, quantity: u128) -> Option<u128> {
+        (self.0 as u128).checked_mul(quantity)
+    }
+
+    // whether this is a whole number of `tick`s
+    pub fn is_multiple_of(self, tick: Price) -> bool {
+        self.0.is_multiple_of(tick.0)
+    }
+
+    // notional in price units back to a float, for averages and reporting
+    pub fn notional_to_f64(notional: u128) -> f64 {
+        notional as f64 / SCALE as f64
+    }
+}
+
+impl From<u64> for Price {
+    fn from(whole: u64) -> Self {
+        Price::new(whole, 0)
+    }
+}
+
+impl FromStr for Price {
+    type Err = ParsePriceError;
+
+    // plain decimal notation: "101", "101.25", ".5"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+        if whole.is_empty() && frac.is_empty() {
+            return Err(ParsePriceError::Empty);
+        }
+        if !whole.chars().chain(frac.chars()).all(|c| c.is_ascii_digit()) {
+            return Err(ParsePriceError::InvalidDigit);
+        }
+        if frac.len() > Price::DECIMALS as usize {
+            return Err(ParsePriceError::TooManyDecimals);
+        }
+
+        let whole: u64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| ParsePriceError::Overflow)?
+        };
+        let frac_units = format!("{:0<width$}", frac, width = Price::DECIMALS as usize)
+            .parse::<u64>()
+            .map_err(|_| ParsePriceError::InvalidDigit)?;
+
+        whole
+            .checked_mul(SCALE)
+            .and_then(|units| units.checked_add(frac_units))
+            .map(Price)
+            .ok_or(ParsePriceError::Overflow)
+    }
+}
+
+// shortest exact form by default, `{:.2}` rounds half up (or pads) to 2 places
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let places = match f.precision() {
+            Some(places) => places.min(Price::DECIMALS as usize),
+            None => self.decimal_places() as usize,
+        };
+
+        let step = 10u128.pow(Price::DECIMALS - places as u32);
+        let rounded = (self.0 as u128 + step / 2) / step; // u128 so rounding MAX can't overflow
+        let scale = 10u128.pow(places as u32);
+        let whole = rounded / scale;
+        let pad = f.precision().unwrap_or(0).saturating_sub(places);
+        let mut s = whole.to_string();
+        if places > 0 {
+            s += &format!(".{:0places$}", rounded % scale, places = places);
+        }
+        s.extend(std::iter::repeat_n('0', pad));
+        // honours width, fill and alignment like a number does. `pad` would also cut the
+        // string down to the precision
+        f.pad_integral(true, "", &s)
+    }
+}
+
+// decimal strings in JSON, raw units in binary formats
+impl Serialize for Price {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_u64(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(PriceVisitor)
+        } else {
+            u64::deserialize(deserializer).map(Price)
+        }
+    }
+}
+
+// JSON clients may send "101.25", 101.25 or 101
+struct PriceVisitor;
+
+impl Visitor<'_> for PriceVisitor {
+    type Value = Price;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a non-negative decimal price")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Price, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Price, E> {
+        Price::from_units(v)
+            .checked_mul(SCALE)
+            .ok_or_else(|| E::custom(ParsePriceError::Overflow))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Price, E> {
+        let v = u64::try_from(v).map_err(|_| E::custom(ParsePriceError::InvalidDigit))?;
+        self.visit_u64(v)
+    }
+
+    // go through the shortest decimal form so 101.25 doesn't pick up float noise
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Price, E> {
+        self.visit_str(&v.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let price: Price = "101.25".parse().unwrap();
+        assert_eq!(price, Price::new(10125, 2));
+        assert_eq!(price.to_string(), "101.25");
+        assert_eq!(format!("{:.4}", price), "101.2500");
+        assert_eq!(format!("{:.1}", price), "101.3");
+        assert_eq!(format!("{:.0}", Price::from(7)), "7");
+        assert_eq!(format!("[{:>8}]", price), "[  101.25]");
+        assert_eq!(format!("[{:<8.1}]", price), "[101.3   ]");
+        assert_eq!(format!("[{:9.3}]", price), "[  101.250]");
+        assert_eq!(format!("[{:*^6}]", Price::from(7)), "[**7***]");
+        assert_eq!(Price::from(100).to_string(), "100");
+        assert_eq!(".5".parse::<Price>().unwrap(), Price::new(5, 1));
+        assert_eq!(price.decimal_places(), 2);
+        assert_eq!(Price::from(100).decimal_places(), 0);
+        assert_eq!(Price::from_units(1).decimal_places(), Price::DECIMALS);
+        assert_eq!(Price::MAX.to_string(), "184467440737.09551615");
+
+        assert_eq!("".parse::<Price>(), Err(ParsePriceError::Empty));
+        assert_eq!("1.2.3".parse::<Price>(), Err(ParsePriceError::InvalidDigit));
+        assert_eq!("-1".parse::<Price>(), Err(ParsePriceError::InvalidDigit));
+        assert_eq!("0.123456789".parse::<Price>(), Err(ParsePriceError::TooManyDecimals));
+        assert_eq!("999999999999".parse::<Price>(), Err(ParsePriceError::Overflow));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let tick = Price::new(1, 2);
+        assert_eq!(Price::from(10).checked_sub(tick), Some(Price::new(999, 2)));
+        assert_eq!(Price::ZERO.checked_sub(tick), None);
+        assert_eq!(Price::MAX.checked_add(tick), None);
+        assert_eq!(Price::new(25, 1).checked_notional(4), Some(Price::from(10).units() as u128));
+        assert!(Price::new(1025, 2).is_multiple_of(Price::new(5, 2)));
+        assert!(!Price::new(1025, 2).is_multiple_of(Price::new(1, 1)));
+        assert_eq!(Price::from_f64(10.239), Some(Price::new(10239, 3)));
+        assert_eq!(Price::from_f64(-1.0), None);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let price = Price::new(10125, 2);
+        assert_eq!(serde_json::to_string(&price).unwrap(), "\"101.25\"");
+        assert_eq!(serde_json::from_str::<Price>("\"101.25\"").unwrap(), price);
+        assert_eq!(serde_json::from_str::<Price>("101.25").unwrap(), price);
+        assert_eq!(serde_json::from_str::<Price>("101").unwrap(), Price::from(101));
+        assert!(serde_json::from_str::<Price>("-1").is_err());
+    }
+}
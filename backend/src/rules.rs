@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{NewOrder, OrderBookError, OrderType, Price};
+
+// what a book accepts, checked on every submit and amend
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct TradingRules {
+    pub tick_size: Price, // prices must be a multiple of this
+    pub lot_size: u128,   // quantities must be a multiple of this
+    pub min_quantity: u128,
+    pub max_quantity: u128,
+    pub min_notional: Price, // price * quantity, limit orders only
+    #[serde(default = "max_decimals")]
+    pub decimals: u32, // the book's price scale, prices with more places are rejected
+}
+
+fn max_decimals() -> u32 {
+    Price::DECIMALS
+}
+
+impl Default for TradingRules {
+    fn default() -> Self {
+        TradingRules {
+            tick_size: Price::from_units(1),
+            lot_size: 1,
+            min_quantity: 1,
+            max_quantity: u128::MAX,
+            min_notional: Price::ZERO,
+            decimals: Price::DECIMALS,
+        }
+    }
+}
+
+impl TradingRules {
+    // zero increments would mean "anything goes", which is what 1 already means
+    pub fn new(tick_size: impl Into<Price>, lot_size: u128) -> Self {
+        let lot_size = lot_size.max(1);
+        TradingRules {
+            tick_size: tick_size.into().max(Price::from_units(1)),
+            lot_size,
+            min_quantity: lot_size,
+            ..TradingRules::default()
+        }
+    }
+
+    pub fn with_quantity_limits(mut self, min_quantity: u128, max_quantity: u128) -> Self {
+        self.min_quantity = min_quantity;
+        self.max_quantity = max_quantity;
+        self
+    }
+
+    pub fn with_min_notional(mut self, min_notional: impl Into<Price>) -> Self {
+        self.min_notional = min_notional.into();
+        self
+    }
+
+    // quote prices with `decimals` places, at most Price::DECIMALS. a tick finer than
+    // that is raised to one unit of the scale
+    pub fn with_decimals(mut self, decimals: u32) -> Self {
+        self.decimals = decimals.min(Price::DECIMALS);
+        self.tick_size = self.tick_size.max(Price::new(1, self.decimals));
+        self
+    }
+
+    pub fn check_order(&self, order: &NewOrder) -> Result<(), OrderBookError> {
+        let priced = matches!(order.order_type, OrderType::Limit | OrderType::StopLimit);
+        if priced {
+            self.check_price(order.price)?;
+        }
+        if let Some(trigger) = order.trigger_price {
+            self.check_price(trigger)?;
+        }
+        if let Some(peak) = order.display_quantity
+            && !peak.is_multiple_of(self.lot_size)
+        {
+            return Err(OrderBookError::QuantityNotOnLot(peak));
+        }
+        self.check_quantity(order.quantity)?;
+        if priced {
+            self.check_notional(order.price, order.quantity)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_amend(&self, price: Price, quantity: u128) -> Result<(), OrderBookError> {
+        self.check_price(price)?;
+        self.check_quantity(quantity)?;
+        self.check_notional(price, quantity)
+    }
+
+    fn check_price(&self, price: Price) -> Result<(), OrderBookError> {
+        if price.decimal_places() > self.decimals {
+            return Err(OrderBookError::TooManyDecimals(price));
+        }
+        if !price.is_multiple_of(self.tick_size) {
+            return Err(OrderBookError::PriceNotOnTick(price));
+        }
+        Ok(())
+    }
+
+    fn check_quantity(&self, quantity: u128) -> Result<(), OrderBookError> {
+        if !quantity.is_multiple_of(self.lot_size) {
+            return Err(OrderBookError::QuantityNotOnLot(quantity));
+        }
+        if quantity < self.min_quantity {
+            return Err(OrderBookError::QuantityBelowMinimum(quantity));
+        }
+        if quantity > self.max_quantity {
+            return Err(OrderBookError::QuantityAboveMaximum(quantity));
+        }
+        Ok(())
+    }
+
+    fn check_notional(&self, price: Price, quantity: u128) -> Result<(), OrderBookError> {
+        // an overflowing notional is certainly above the minimum
+        match price.checked_notional(quantity) {
+            Some(notional) if notional < self.min_notional.units() as u128 => Err(
+                OrderBookError::NotionalBelowMinimum(Price::from_units(notional as u64)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderBook, Side};
+
+    fn rules() -> TradingRules {
+        TradingRules::new(5, 10)
+            .with_quantity_limits(20, 1000)
+            .with_min_notional(5000)
+    }
+
+    #[test]
+    fn test_rejection_reasons() {
+        let r = rules();
+        assert_eq!(r.check_order(&NewOrder::limit(Side::Bid, 500, 20)), Ok(()));
+        assert_eq!(
+            r.check_order(&NewOrder::limit(Side::Bid, 502, 20)),
+            Err(OrderBookError::PriceNotOnTick(Price::from(502)))
+        );
+        assert_eq!(
+            r.check_order(&NewOrder::limit(Side::Bid, 500, 25)),
+            Err(OrderBookError::QuantityNotOnLot(25))
+        );
+        assert_eq!(
+            r.check_order(&NewOrder::limit(Side::Bid, 500, 10)),
+            Err(OrderBookError::QuantityBelowMinimum(10))
+        );
+        assert_eq!(
+            r.check_order(&NewOrder::limit(Side::Bid, 500, 1010)),
+            Err(OrderBookError::QuantityAboveMaximum(1010))
+        );
+        assert_eq!(
+            r.check_order(&NewOrder::limit(Side::Bid, 100, 20)),
+            Err(OrderBookError::NotionalBelowMinimum(Price::from(2000)))
+        );
+        assert_eq!(
+            r.check_order(&NewOrder::stop_market(Side::Bid, 503, 20)),
+            Err(OrderBookError::PriceNotOnTick(Price::from(503)))
+        );
+        assert_eq!(
+            r.check_order(&NewOrder::limit(Side::Bid, 500, 100).iceberg(15)),
+            Err(OrderBookError::QuantityNotOnLot(15))
+        );
+
+        // market orders have no price to check a tick or notional against
+        assert_eq!(r.check_order(&NewOrder::market(Side::Bid, 20)), Ok(()));
+    }
+
+    #[test]
+    fn test_decimals_set_the_price_scale() {
+        let cents = TradingRules::new(Price::from_units(1), 1).with_decimals(2);
+        assert_eq!(cents.tick_size, Price::new(1, 2));
+        let limit = |price| NewOrder::limit(Side::Bid, price, 1);
+        assert_eq!(cents.check_order(&limit(Price::new(10125, 2))), Ok(()));
+        assert_eq!(
+            cents.check_order(&limit(Price::new(101255, 3))),
+            Err(OrderBookError::TooManyDecimals(Price::new(101255, 3)))
+        );
+
+        // a coarser tick still applies on top of the scale
+        let nickels = TradingRules::new(Price::new(5, 2), 1).with_decimals(2);
+        assert_eq!(nickels.tick_size, Price::new(5, 2));
+        assert_eq!(
+            nickels.check_order(&limit(Price::new(10101, 2))),
+            Err(OrderBookError::PriceNotOnTick(Price::new(10101, 2)))
+        );
+        let whole = TradingRules::default().with_decimals(0);
+        assert_eq!(
+            whole.check_amend(Price::new(15, 1), 1),
+            Err(OrderBookError::TooManyDecimals(Price::new(15, 1)))
+        );
+    }
+
+    #[test]
+    fn test_book_enforces_rules() {
+        let mut a = OrderBook::build_with_rules(rules());
+        assert_eq!(
+            a.submit(NewOrder::limit(Side::Ask, 503, 20)),
+            Err(OrderBookError::PriceNotOnTick(Price::from(503)))
+        );
+        assert_eq!(a.best_ask(), None);
+
+        let id = a
+            .submit(NewOrder::limit(Side::Ask, 505, 20))
+            .unwrap()
+            .order_id;
+        assert_eq!(
+            a.amend(id, 507, 20),
+            Err(OrderBookError::PriceNotOnTick(Price::from(507)))
+        );
+        assert_eq!(
+            a.amend(id, 505, 15),
+            Err(OrderBookError::QuantityNotOnLot(15))
+        );
+
+        // repricing moves a whole tick away from the opposite side
+        a.set_post_only_mode(crate::PostOnlyMode::Reprice);
+        let report = a
+            .submit(NewOrder::limit(Side::Bid, 505, 20).post_only())
+            .unwrap();
+        assert_eq!(a.get_buy_order(report.order_id).unwrap().price(), Price::from(500));
+    }
+}
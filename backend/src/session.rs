@@ -0,0 +1,124 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::SECS_PER_DAY;
+
+// where a book is in its trading day. only Continuous matches on arrival, every other
+// state collects orders (if it accepts any) for the next uncross
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    PreOpen,        // order entry before the open
+    OpeningAuction, // the book is frozen except for new orders, uncrosses into Continuous
+    #[default]
+    Continuous,
+    Halted,         // trading stopped, orders can only be cancelled
+    ClosingAuction, // uncrosses into Closed
+    Closed,
+}
+
+// what a client can do to a book
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionAction {
+    Submit,
+    Amend,
+    Cancel,
+}
+
+// a daily timetable of session states, by time since UTC midnight
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    entries: Vec<(Duration, SessionState)>, // sorted by time of day
+}
+
+impl SessionState {
+    pub fn allows(self, action: SessionAction) -> bool {
+        match self {
+            SessionState::PreOpen | SessionState::Continuous | SessionState::ClosingAuction => {
+                true
+            }
+            SessionState::OpeningAuction => action == SessionAction::Submit,
+            SessionState::Halted | SessionState::Closed => action == SessionAction::Cancel,
+        }
+    }
+
+    pub fn can_transition_to(self, next: SessionState) -> bool {
+        use SessionState::*;
+        match self {
+            PreOpen => matches!(next, OpeningAuction | Continuous | Halted | Closed),
+            OpeningAuction => matches!(next, Continuous | Halted | Closed),
+            Continuous => matches!(next, Halted | ClosingAuction | Closed),
+            Halted => matches!(next, OpeningAuction | Continuous | Closed),
+            ClosingAuction => matches!(next, Halted | Closed),
+            Closed => next == PreOpen,
+        }
+    }
+
+    // whether moving from `self` to `next` ends a call auction with an uncross. a halted
+    // book that reopens straight into continuous trading uncrosses too, so it never
+    // starts matching while crossed
+    pub(crate) fn uncrosses_into(self, next: SessionState) -> bool {
+        next == SessionState::Continuous
+            || (self == SessionState::ClosingAuction && next == SessionState::Closed)
+    }
+}
+
+impl Schedule {
+    // `entries` are (time since UTC midnight, state entered at that time). before the
+    // first entry of a day the last one of the previous day still applies
+    pub fn daily(mut entries: Vec<(Duration, SessionState)>) -> Self {
+        entries.retain(|(offset, _)| offset.as_secs() < SECS_PER_DAY);
+        entries.sort_by_key(|(offset, _)| *offset);
+        Schedule { entries }
+    }
+
+    // None for an empty schedule
+    pub fn state_at(&self, time: SystemTime) -> Option<SessionState> {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let time_of_day = Duration::from_secs(since_epoch.as_secs() % SECS_PER_DAY)
+            + Duration::from_nanos(since_epoch.subsec_nanos() as u64);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(offset, _)| *offset <= time_of_day)
+            .or(self.entries.last())
+            .map(|(_, state)| *state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_actions_and_transitions() {
+        assert!(SessionState::PreOpen.allows(SessionAction::Amend));
+        assert!(!SessionState::OpeningAuction.allows(SessionAction::Cancel));
+        assert!(SessionState::Halted.allows(SessionAction::Cancel));
+        assert!(!SessionState::Closed.allows(SessionAction::Submit));
+
+        assert!(SessionState::Closed.can_transition_to(SessionState::PreOpen));
+        assert!(!SessionState::Closed.can_transition_to(SessionState::Continuous));
+        assert!(SessionState::OpeningAuction.uncrosses_into(SessionState::Continuous));
+        assert!(!SessionState::Halted.uncrosses_into(SessionState::Closed));
+    }
+
+    #[test]
+    fn test_daily_schedule() {
+        let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+        let schedule = Schedule::daily(vec![
+            (hours(16), SessionState::ClosingAuction),
+            (hours(8), SessionState::PreOpen),
+            (hours(9), SessionState::Continuous),
+            (hours(17), SessionState::Closed),
+        ]);
+        let day = SystemTime::UNIX_EPOCH + Duration::from_secs(20_000 * SECS_PER_DAY);
+        assert_eq!(schedule.state_at(day + hours(3)), Some(SessionState::Closed));
+        assert_eq!(schedule.state_at(day + hours(8)), Some(SessionState::PreOpen));
+        assert_eq!(schedule.state_at(day + hours(12)), Some(SessionState::Continuous));
+        assert_eq!(schedule.state_at(day + hours(16)), Some(SessionState::ClosingAuction));
+        assert_eq!(Schedule::default().state_at(day), None);
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Exchange;
+use crate::journal::{ARCHIVE_DIR, invalid, sync_dir};
+
+// start of every snapshot file, the last byte is the format version
+const MAGIC: &[u8; 8] = b"CLOBSNP\x01";
+
+// every book of an exchange, with all their orders, id counters and sequence numbers,
+// as of the journal entry at `offset`. recovery loads the latest one and replays only
+// the journal after it. the books' event history is left out, their sequences aren't
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub offset: u64,
+    pub exchange: Exchange,
+}
+
+impl Snapshot {
+    pub fn new(offset: u64, mut exchange: Exchange) -> Self {
+        exchange.forget_events();
+        Snapshot { offset, exchange }
+    }
+
+    // compact binary encoding, prices as raw units
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let body = postcard::to_stdvec(self).map_err(|err| invalid(err.to_string()))?;
+        Ok([MAGIC.as_slice(), &body].concat())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Snapshot> {
+        let Some(body) = bytes.strip_prefix(MAGIC) else {
+            return Err(invalid("not a snapshot, or from another version".to_string()));
+        };
+        postcard::from_bytes(body).map_err(|err| invalid(err.to_string()))
+    }
+
+    // write the snapshot into `dir` next to the journal, then archive older snapshots.
+    // it only takes the place of the last one once it is whole and on disk
+    pub fn save(&self, dir: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let dir = dir.as_ref();
+        let path = dir.join(file_name(self.offset));
+        let partial = path.with_extension("tmp");
+        let mut file = File::create(&partial)?;
+        file.write_all(&self.to_bytes()?)?;
+        file.sync_all()?;
+        fs::rename(&partial, &path)?;
+        sync_dir(dir)?;
+
+        let archive = dir.join(ARCHIVE_DIR);
+        for (offset, older) in snapshots(dir)? {
+            if offset < self.offset {
+                fs::create_dir_all(&archive)?;
+                fs::rename(&older, archive.join(file_name(offset)))?;
+            }
+        }
+        Ok(path)
+    }
+
+    // the newest snapshot in `dir`, if there is one. an unreadable one is an error
+    // rather than a reason to fall back, the journal it would need may be archived
+    pub fn latest(dir: impl AsRef<Path>) -> io::Result<Option<Snapshot>> {
+        let dir = dir.as_ref();
+        if !dir.exists() {
+            return Ok(None);
+        }
+        let Some((_, path)) = snapshots(dir)?.pop() else {
+            return Ok(None);
+        };
+        Snapshot::from_bytes(&fs::read(path)?).map(Some)
+    }
+}
+
+fn file_name(offset: u64) -> String {
+    format!("snapshot-{offset:020}.bin")
+}
+
+// snapshot files in `dir` by offset
+fn snapshots(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut snapshots = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let offset = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix("snapshot-")?.strip_suffix(".bin"))
+            .and_then(|offset| offset.parse().ok());
+        if let Some(offset) = offset {
+            snapshots.push((offset, path));
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::tests::temp_dir;
+    use crate::{Command, Instrument, Journal, NewOrder, PriceBands, Side, TimeInForce};
+    use std::time::{Duration, SystemTime};
+
+    fn exchange() -> Exchange {
+        let mut ex = Exchange::build();
+        ex.list(Instrument::new("ABC", 1, 1)).unwrap();
+        let bands = PriceBands::default().with_static(1000);
+        ex.list(Instrument::new("XYZ", 1, 1).with_circuit_breakers(bands)).unwrap();
+        ex
+    }
+
+    #[test]
+    fn test_snapshot_keeps_the_whole_book() {
+        let mut ex = exchange();
+        let day = TimeInForce::Day;
+        ex.submit("ABC", NewOrder::limit(Side::Ask, 100, 10).iceberg(3)).unwrap();
+        ex.submit("ABC", NewOrder::limit(Side::Bid, 99, 4).with_time_in_force(day)).unwrap();
+        ex.submit("ABC", NewOrder::stop_market(Side::Bid, 101, 2).for_account(5)).unwrap();
+        ex.submit("ABC", NewOrder::market(Side::Bid, 4)).unwrap();
+        ex.submit("XYZ", NewOrder::limit(Side::Bid, 50, 1)).unwrap();
+
+        let snapshot = Snapshot::new(7, ex.clone());
+        let restored = Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored, snapshot);
+        let book = restored.exchange.book("ABC").unwrap();
+        assert_eq!(*book.get_tot_orders(), 4);
+        assert_eq!(book.sequence(), ex.book("ABC").unwrap().sequence());
+        assert!(book.events_since(0).is_empty());
+        assert!(Snapshot::from_bytes(b"CLOBSNP\x00").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_and_tail_recover_the_exchange() {
+        let dir = temp_dir("snapshot-recovery");
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let commands = [
+            ("ABC", Command::Submit(NewOrder::limit(Side::Ask, 100, 10).iceberg(3))),
+            ("XYZ", Command::Submit(NewOrder::limit(Side::Bid, 50, 2))),
+            ("ABC", Command::Submit(NewOrder::market(Side::Bid, 4))),
+            ("ABC", Command::Cancel { id: 9 }),
+            ("XYZ", Command::Submit(NewOrder::limit(Side::Ask, 51, 1))),
+            ("ABC", Command::Submit(NewOrder::limit(Side::Bid, 100, 3))),
+        ];
+
+        let mut live = exchange();
+        let (mut journal, _) = Journal::open(&dir, 0).unwrap();
+        for (i, (symbol, command)) in commands.into_iter().enumerate() {
+            let time = start + Duration::from_secs(i as u64);
+            let entry = journal.append(time, symbol, command).unwrap();
+            let _ = live.apply(&entry);
+            if entry.offset == 2 || entry.offset == 4 {
+                journal.roll().unwrap();
+                Snapshot::new(entry.offset, live.clone()).save(&dir).unwrap();
+                journal.archive(entry.offset).unwrap();
+            }
+        }
+        drop(journal);
+
+        let snapshot = Snapshot::latest(&dir).unwrap().unwrap();
+        assert_eq!(snapshot.offset, 4);
+        let (_, tail) = Journal::open(&dir, snapshot.offset).unwrap();
+        assert_eq!(tail.len(), 2);
+        let mut recovered = snapshot.exchange;
+        for entry in &tail {
+            let _ = recovered.apply(entry);
+        }
+        assert_eq!(Snapshot::new(0, recovered), Snapshot::new(0, live));
+        assert!(dir.join(ARCHIVE_DIR).join(file_name(2)).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,26 @@
+export const API = "http://localhost:3000";
+export const DEFAULT_SYMBOL = "ABC";
+
+export type Side = "buy" | "sell";
+export type OrderType = "limit" | "market" | "stop_market" | "stop_limit";
+export type TimeInForce = "gtc" | "ioc" | "fok" | "day" | "gtd";
+
 export interface CreateOrder {
-    buy_order: boolean;
-    price: number;
+    side: Side;
+    type: OrderType;
+    price: number | string; // decimal, "101.25" keeps full precision
     quantity: number;
+    time_in_force?: TimeInForce;
+    expire_at?: number; // unix seconds, required for gtd
+    account?: number;
+    post_only?: boolean;
+    reduce_only?: boolean;
+    trigger_price?: number | string; // required for stop orders
+    display_quantity?: number; // iceberg peak
 }
 
-export async function createOrder(order: CreateOrder) {
-    const res = await fetch("http://localhost:3000/orders", {method: "POST", headers: { "Content-Type": "application/json" }, body: JSON.stringify(order)});
+export async function createOrder(symbol: string, order: CreateOrder) {
+    const res = await fetch(`${API}/instruments/${symbol}/orders`, {method: "POST", headers: { "Content-Type": "application/json" }, body: JSON.stringify(order)});
 
     if (!res.ok) {
         throw new Error(`Failed to create order: ${res.statusText}`)
@@ -1,4 +1,5 @@
 import { useEffect, useState } from "react";
+import { API, DEFAULT_SYMBOL } from "../api";
 
 interface Time {
   secs_since_epoch: number;
@@ -6,22 +7,38 @@ interface Time {
 }
 
 interface Order {
-  buy_order: boolean;
-  price: number;
+  side: "buy" | "sell";
+  order_type: "limit" | "market";
+  price: string; // decimal, e.g. "101.25"
   quantity: number;
   id: number;
   time_created: Time;
 }
 
 interface Transaction {
-  price: number;
+  trade_id: number;
+  price: string;
   quantity: number;
   time: Time;
+  aggressor_side: "buy" | "sell";
+  taker_order_id: number;
+  maker_order_id: number;
+  taker_remaining: number;
+  maker_remaining: number;
 }
 
+type SessionState =
+  | "pre_open"
+  | "opening_auction"
+  | "continuous"
+  | "halted"
+  | "closing_auction"
+  | "closed";
+
 interface OrderBook {
   total_orders: number;
-  buy_orders: Record<string, Order[]>;  // keys are prices ("5", "6", …)
+  session: SessionState;
+  buy_orders: Record<string, Order[]>;  // keys are prices ("5.41", "5.6", …)
   sell_orders: Record<string, Order[]>;
   transactions: Transaction[];
 }
@@ -31,7 +48,7 @@ export default function ClobStats() {
   const [data, setData] = useState<OrderBook | null>(null);
 
   useEffect(() => {
-    fetch("http://localhost:3000/clob-stats")
+    fetch(`${API}/instruments/${DEFAULT_SYMBOL}/clob-stats`)
       .then((res) => res.json())
       .then((json) => setData(json));
   }, []);
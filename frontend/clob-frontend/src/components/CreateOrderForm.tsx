@@ -1,5 +1,5 @@
 import { useState } from "react";
-import { createOrder } from "../api";
+import { createOrder, DEFAULT_SYMBOL, type OrderType, type Side } from "../api";
 
 interface Response {
     responseCode: number,
@@ -9,13 +9,14 @@ interface Response {
 export default function CreateOrderForm() {
     const [price, setPrice] = useState<number>(0);
     const [quantity, setQuantity] = useState<number>(0);
-    const [buyOrder, setBuyOrder] = useState(true);
+    const [side, setSide] = useState<Side>("buy");
+    const [orderType, setOrderType] = useState<OrderType>("limit");
     const [response, setResponse] = useState<Response | null>(null);
 
     async function handleSubmit(e:React.FormEvent) {
         e.preventDefault();
         try {
-            const result = await createOrder({ buy_order: buyOrder, price, quantity });
+            const result = await createOrder(DEFAULT_SYMBOL, { side, type: orderType, price, quantity });
             setResponse(result);
         } catch (err) {
             console.error(err);
@@ -26,16 +27,27 @@ export default function CreateOrderForm() {
         <div className="p-4">
       <form onSubmit={handleSubmit} className="space-y-2">
         <label>
-          Type:
+          Side:
           <select
-            value={buyOrder ? "buy" : "sell"}
-            onChange={(e) => setBuyOrder(e.target.value === "buy")}
+            value={side}
+            onChange={(e) => setSide(e.target.value as Side)}
           >
             <option value="buy">Buy</option>
             <option value="sell">Sell</option>
           </select>
         </label>
 
+        <label>
+          Type:
+          <select
+            value={orderType}
+            onChange={(e) => setOrderType(e.target.value as OrderType)}
+          >
+            <option value="limit">Limit</option>
+            <option value="market">Market</option>
+          </select>
+        </label>
+
         <label>
           Price:
           <input